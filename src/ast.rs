@@ -0,0 +1,471 @@
+use std::{convert::Infallible, mem};
+
+use crate::{functions::{argument_count, call_function, function_to_string, Function}, latex_parse::parse_latex_spans, script::Scope, token_parse::{is_comparison, apply_operator, parse_exp_spans, precedence, Operator, Span, Token}, validation::{add_implicit_tokens_spans, Validator, COMPARISONS, LISTS, STRINGS}};
+
+/// A node of a parsed expression together with the part of the input it was parsed from.
+///
+/// Nothing that works on trees recurses, so a tree may be as deep as the input is long.
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprKind {
+    Num(f32),
+    Var(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+    /// A vector `[1, 2]`, or a matrix when the elements are lists.
+    List(Vec<Expr>),
+    /// A string, see `strings`.
+    Str(String),
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+}
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+    /// The operand of a negation, the operands of an operator, the arguments of a call or the elements of a list.
+    pub fn children(&self) -> impl Iterator<Item = &Expr> {
+        (0..).map_while(|i| self.child(i))
+    }
+    fn child(&self, i: usize) -> Option<&Expr> {
+        match &self.kind {
+            ExprKind::Unary(_, e) if i == 0 => Some(e),
+            ExprKind::Binary(_, left, _) if i == 0 => Some(left),
+            ExprKind::Binary(_, _, right) if i == 1 => Some(right),
+            ExprKind::Call(_, args) | ExprKind::List(args) => args.get(i),
+            _ => None,
+        }
+    }
+    /// Computes a value for every node from the node and the values of its children, in the order
+    /// of `children`. Stops at the first error. Walks the tree with a stack of its own, so any depth works.
+    pub fn try_fold<T, E>(&self, mut f: impl FnMut(&Expr, Vec<T>) -> Result<T, E>) -> Result<T, E> {
+
+        // the nodes from the root to the current one, with the number of their children done
+        let mut path = vec![(self, 0)];
+        let mut values = Vec::new();
+
+        while let Some(&(node, done)) = path.last() {
+            match node.child(done) {
+                Some(child) => {
+                    path.last_mut().unwrap().1 += 1;
+                    path.push((child, 0));
+                },
+                None => {
+                    path.pop();
+                    let children = values.split_off(values.len() - done);
+                    values.push(f(node, children)?);
+                },
+            }
+        }
+        Ok(values.pop().unwrap())
+    }
+    /// Like `try_fold` for a value that is always there.
+    pub fn fold<T>(&self, mut f: impl FnMut(&Expr, Vec<T>) -> T) -> T {
+        let Ok(value) = self.try_fold(|e, children| Ok::<T, Infallible>(f(e, children)));
+        value
+    }
+    /// A copy of the node with `children` in place of its own, in the order of `children`.
+    pub fn with_children(&self, mut children: Vec<Expr>) -> Expr {
+        let kind = match &self.kind {
+            ExprKind::Num(n) => ExprKind::Num(*n),
+            ExprKind::Var(name) => ExprKind::Var(name.clone()),
+            ExprKind::Str(text) => ExprKind::Str(text.clone()),
+            ExprKind::Unary(o, _) => ExprKind::Unary(*o, Box::new(children.pop().unwrap())),
+            ExprKind::Binary(o, ..) => {
+                let right = children.pop().unwrap();
+                let left = children.pop().unwrap();
+                ExprKind::Binary(*o, Box::new(left), Box::new(right))
+            },
+            ExprKind::Call(f, _) => ExprKind::Call(*f, children),
+            ExprKind::List(_) => ExprKind::List(children),
+        };
+        Expr::new(kind, self.span.clone())
+    }
+    /// Lowers the tree back into tokens with every implicit token written out,
+    /// the form `linear_eval`, `evaluate_exp` and `compile` take.
+    pub fn to_tokens(&self) -> Vec<Token> {
+
+        let mut tokens = Vec::new();
+        // what is left to write, the last part comes first
+        let mut parts = vec![Part::Expr(self)];
+
+        while let Some(part) = parts.pop() {
+            let e = match part {
+                Part::Token(t) => {
+                    tokens.push(t);
+                    continue;
+                },
+                Part::Expr(e) => e,
+            };
+            let mut next = Vec::new();
+
+            match &e.kind {
+                ExprKind::Num(n) => next.push(Part::Token(Token::Number(*n))),
+                ExprKind::Var(name) => next.push(Part::Token(Token::Variable(name.clone()))),
+                ExprKind::Str(text) => next.push(Part::Token(Token::Str(text.clone()))),
+                ExprKind::Unary(UnaryOp::Neg, e) => {
+                    next.extend([Part::Token(Token::Bracket(true)), Part::Token(Token::Number(0.0)), Part::Token(Token::Operator(Operator::Minus))]);
+                    push_bracketed(&mut next, e);
+                    next.push(Part::Token(Token::Bracket(false)));
+                },
+                ExprKind::Binary(o, left, right) => {
+                    push_bracketed(&mut next, left);
+                    next.push(Part::Token(Token::Operator(*o)));
+                    push_bracketed(&mut next, right);
+                },
+                ExprKind::Call(f, args) => {
+                    next.extend([Part::Token(Token::Function(*f)), Part::Token(Token::Bracket(true))]);
+                    for (i, arg) in args.iter().enumerate() {
+                        if i > 0 { next.push(Part::Token(Token::Comma)); }
+                        next.push(Part::Expr(arg));
+                    }
+                    next.push(Part::Token(Token::Bracket(false)));
+                },
+                ExprKind::List(elements) => {
+                    next.push(Part::Token(Token::List(true)));
+                    for (i, e) in elements.iter().enumerate() {
+                        if i > 0 { next.push(Part::Token(Token::Comma)); }
+                        next.push(Part::Expr(e));
+                    }
+                    next.push(Part::Token(Token::List(false)));
+                },
+            }
+            parts.extend(next.into_iter().rev());
+        }
+        tokens
+    }
+    /// Compares the trees without their spans.
+    pub fn same_tree(&self, other: &Expr) -> bool {
+        self.same(other, false)
+    }
+    fn same(&self, other: &Expr, spans: bool) -> bool {
+
+        let mut pairs = vec![(self, other)];
+
+        while let Some((a, b)) = pairs.pop() {
+            let same = (!spans || a.span == b.span) && match (&a.kind, &b.kind) {
+                (ExprKind::Num(x), ExprKind::Num(y)) => x == y,
+                (ExprKind::Var(x), ExprKind::Var(y)) | (ExprKind::Str(x), ExprKind::Str(y)) => x == y,
+                (ExprKind::Unary(o1, _), ExprKind::Unary(o2, _)) => o1 == o2,
+                (ExprKind::Binary(o1, ..), ExprKind::Binary(o2, ..)) => o1 == o2,
+                (ExprKind::Call(f1, a1), ExprKind::Call(f2, a2)) => f1 == f2 && a1.len() == a2.len(),
+                (ExprKind::List(a1), ExprKind::List(a2)) => a1.len() == a2.len(),
+                _ => false,
+            };
+            if !same {
+                return false;
+            }
+            pairs.extend(a.children().zip(b.children()));
+        }
+        true
+    }
+    /// Takes the kind out of the node, which can't be moved out of since the node implements `Drop`.
+    pub fn into_kind(mut self) -> ExprKind {
+        mem::replace(&mut self.kind, ExprKind::Num(0.0))
+    }
+    fn is_leaf(&self) -> bool {
+        matches!(self.kind, ExprKind::Num(_) | ExprKind::Var(_) | ExprKind::Str(_))
+    }
+}
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        self.same(other, true)
+    }
+}
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        self.fold(Expr::with_children)
+    }
+}
+/// Dropping the children of a node in its own drop would recurse, so every node below
+/// the root is taken out of the tree and dropped from a stack instead.
+impl Drop for Expr {
+    fn drop(&mut self) {
+
+        let mut stack = Vec::new();
+        take_children(self, &mut stack);
+
+        while let Some(mut e) = stack.pop() {
+            take_children(&mut e, &mut stack);
+        }
+    }
+}
+/// Moves the children that have children of their own onto the stack, leaves are dropped right away.
+fn take_children(e: &mut Expr, stack: &mut Vec<Expr>) {
+
+    let mut take = |e: &mut Box<Expr>| {
+        if !e.is_leaf() {
+            stack.push(mem::replace(&mut **e, Expr::new(ExprKind::Num(0.0), 0..0)));
+        }
+    };
+
+    match &mut e.kind {
+        ExprKind::Unary(_, e) => take(e),
+        ExprKind::Binary(_, left, right) => {
+            take(left);
+            take(right);
+        },
+        ExprKind::Call(_, args) | ExprKind::List(args) => stack.extend(args.drain(..).filter(|e| !e.is_leaf())),
+        _ => {},
+    }
+}
+/// A part of what `Expr::to_tokens` writes.
+enum Part<'a> {
+    Expr(&'a Expr),
+    Token(Token),
+}
+/// Pushes the expression, bracketed if it is an operation.
+fn push_bracketed<'a>(parts: &mut Vec<Part<'a>>, e: &'a Expr) {
+    if let ExprKind::Binary(..) = e.kind {
+        parts.extend([Part::Token(Token::Bracket(true)), Part::Expr(e), Part::Token(Token::Bracket(false))]);
+    }
+    else {
+        parts.push(Part::Expr(e));
+    }
+}
+/// Parses the input into an expression tree. A leading minus is kept as the `0 - x`
+/// that `add_implicit_tokens` produces, implicit multiplications are explicit nodes.
+pub fn parse_ast(input: &str) -> Result<Expr, String> {
+    ast_from_tokens(parse_exp_spans(input)?)
+}
+/// Like `parse_ast`, brackets, calls and lists may be nested at most `max_depth` levels deep.
+pub fn parse_ast_with_depth(input: &str, max_depth: usize) -> Result<Expr, String> {
+    validated_ast(parse_exp_spans(input)?, Validator::with_max_depth(max_depth))
+}
+/// Like `parse_ast` for LaTeX math, see `parse_latex`.
+pub fn parse_latex_ast(input: &str) -> Result<Expr, String> {
+    ast_from_tokens(parse_latex_spans(input)?)
+}
+pub(crate) fn ast_from_tokens(tokens: (Vec<Token>, Vec<Span>)) -> Result<Expr, String> {
+    validated_ast(tokens, Validator::new())
+}
+/// Like `ast_from_tokens`, lists are allowed.
+pub(crate) fn ast_from_list_tokens(tokens: (Vec<Token>, Vec<Span>)) -> Result<Expr, String> {
+    validated_ast(tokens, Validator::with_lists())
+}
+/// Like `ast_from_tokens`, strings and the string functions are allowed.
+pub(crate) fn ast_from_string_tokens(tokens: (Vec<Token>, Vec<Span>)) -> Result<Expr, String> {
+    validated_ast(tokens, Validator::with_strings())
+}
+/// Like `ast_from_tokens`, the functions of complex numbers are allowed.
+pub(crate) fn ast_from_complex_tokens(tokens: (Vec<Token>, Vec<Span>)) -> Result<Expr, String> {
+    validated_ast(tokens, Validator::with_complex())
+}
+fn validated_ast((mut tokens, mut spans): (Vec<Token>, Vec<Span>), mut validator: Validator) -> Result<Expr, String> {
+
+    for t in &tokens {
+        validator.push(t)?;
+    }
+    validator.finish()?;
+
+    add_implicit_tokens_spans(&mut tokens, &mut spans);
+
+    Ok(create_ast(tokens, spans))
+}
+enum Pending {
+    Operator(Operator),
+    // the function with the start of its name, and the number of values before the bracket
+    Bracket(Option<(Function, usize)>, usize),
+    // the start of the list and the number of values before it
+    List(usize, usize),
+}
+/// Builds the tree from validated tokens with implicit tokens added.
+fn create_ast(tokens: Vec<Token>, spans: Vec<Span>) -> Expr {
+
+    let mut values: Vec<Expr> = Vec::new();
+    let mut stack = Vec::new();
+    let mut function = None;
+
+    for (t, span) in tokens.into_iter().zip(spans) {
+        match t {
+            Token::Number(n) => values.push(Expr::new(ExprKind::Num(n), span)),
+            Token::Variable(name) => values.push(Expr::new(ExprKind::Var(name), span)),
+            Token::Str(text) => values.push(Expr::new(ExprKind::Str(text), span)),
+            Token::Function(f) => function = Some((f, span.start)),
+            Token::Operator(o) => {
+                while let Some(Pending::Operator(top)) = stack.last() {
+                    // exponentiation is right associative
+                    if precedence(top) < precedence(&o) || (precedence(top) == precedence(&o) && o == Operator::Pow) {
+                        break;
+                    }
+                    push_binary(&mut values, *top);
+                    stack.pop();
+                }
+                stack.push(Pending::Operator(o));
+            },
+            Token::Comma => {
+                while let Some(Pending::Operator(top)) = stack.last() {
+                    push_binary(&mut values, *top);
+                    stack.pop();
+                }
+            },
+            Token::Bracket(b) => {
+                if b {
+                    stack.push(Pending::Bracket(function.take(), values.len()));
+                    continue;
+                }
+                loop {
+                    match stack.pop() {
+                        Some(Pending::Operator(top)) => push_binary(&mut values, top),
+                        Some(Pending::Bracket(f, first)) => {
+                            if let Some((f, start)) = f {
+                                let args = values.split_off(first);
+                                values.push(Expr::new(ExprKind::Call(f, args), start..span.end));
+                            }
+                            break;
+                        },
+                        Some(Pending::List(..)) | None => unreachable!("brackets are validated"),
+                    }
+                }
+            },
+            Token::List(b) => {
+                if b {
+                    stack.push(Pending::List(span.start, values.len()));
+                    continue;
+                }
+                loop {
+                    match stack.pop() {
+                        Some(Pending::Operator(top)) => push_binary(&mut values, top),
+                        Some(Pending::List(start, first)) => {
+                            let elements = values.split_off(first);
+                            values.push(Expr::new(ExprKind::List(elements), start..span.end));
+                            break;
+                        },
+                        Some(Pending::Bracket(..)) | None => unreachable!("brackets are validated"),
+                    }
+                }
+            },
+        }
+    }
+    while let Some(Pending::Operator(top)) = stack.pop() {
+        push_binary(&mut values, top);
+    }
+
+    values.pop().unwrap()
+}
+fn push_binary(values: &mut Vec<Expr>, o: Operator) {
+
+    let right = values.pop().unwrap();
+    let left = values.pop().unwrap();
+    let span = left.span.start.min(right.span.start)..right.span.end;
+
+    values.push(Expr::new(ExprKind::Binary(o, Box::new(left), Box::new(right)), span));
+}
+/// Checks a call like `validator` would, trees built by hand don't have to be valid.
+pub(crate) fn check_call(f: &Function, args: &[Expr], validator: &Validator) -> Result<(), String> {
+    validator.check_function(f)?;
+    check_arguments(f, args)
+}
+/// Checks that a call has as many arguments as its function takes.
+pub(crate) fn check_arguments(f: &Function, args: &[Expr]) -> Result<(), String> {
+    if args.len() != argument_count(f) {
+        return Err(format!("Function \"{}\" expects {} argument/s", function_to_string(f), argument_count(f)));
+    }
+    Ok(())
+}
+/// Evaluates the tree, variables are looked up in `scope`.
+pub fn evaluate_ast(expr: &Expr, scope: &Scope) -> Result<f32, String> {
+    expr.try_fold(|e, values: Vec<f32>| match &e.kind {
+        ExprKind::Num(n) => Ok(*n),
+        ExprKind::Var(name) => scope.get(name).ok_or_else(|| format!("Unknown variable: \"{}\"", name)),
+        ExprKind::Unary(UnaryOp::Neg, _) => Ok(-values[0]),
+        ExprKind::Binary(Operator::MatMul, ..) => Err(LISTS.to_string()),
+        ExprKind::Binary(o, ..) if is_comparison(o) => Err(COMPARISONS.to_string()),
+        ExprKind::Binary(o, ..) => Ok(apply_operator(o, values[0], values[1])),
+        ExprKind::Call(f, args) => {
+            check_call(f, args, &Validator::new())?;
+            Ok(call_function(f, &values))
+        },
+        ExprKind::List(_) => Err(LISTS.to_string()),
+        ExprKind::Str(_) => Err(STRINGS.to_string()),
+    })
+}
+
+#[test]
+fn ast_parsing() {
+    let num = |n: f32, span: Span| Box::new(Expr::new(ExprKind::Num(n), span));
+
+    let expr = parse_ast("-x^2").unwrap();
+    let power = Expr::new(ExprKind::Binary(Operator::Pow, Box::new(Expr::new(ExprKind::Var("x".to_string()), 1..2)), num(2.0, 3..4)), 1..4);
+    assert_eq!(expr, Expr::new(ExprKind::Binary(Operator::Minus, num(0.0, 0..0), Box::new(power)), 0..4));
+
+    let expr = parse_ast("2 max(1, 3)").unwrap();
+    match &expr.kind {
+        ExprKind::Binary(Operator::Mul, left, right) => {
+            assert_eq!(left.span, 0..1);
+            assert_eq!(right.span, 2..11);
+            assert!(matches!(&right.kind, ExprKind::Call(Function::Max, args) if args.len() == 2));
+        },
+        _ => panic!("expected implicit multiplication"),
+    }
+
+    let mut scope = Scope::new();
+    scope.set("x", 2.0);
+
+    for exp in ["2 / 4 / 2", "2^3^2", "(1 + 2 * 3) * 4 (1 + 2 + 3) * 5", "-.5(1+2)(-3+4) * 5 + 3 * 2(1*20)", "sqrt(x + 2) - min(-x, x)^2"] {
+        let expr = parse_ast(exp).unwrap();
+        let mut tokens = expr.to_tokens();
+        crate::script::resolve_variables(&mut tokens, &scope).unwrap();
+
+        assert_eq!(evaluate_ast(&expr, &scope), crate::eval_exp_in(exp, &scope), "{}", exp);
+        assert_eq!(crate::bytecode::evaluate_bytecode(&tokens), crate::eval_exp_in(exp, &scope).unwrap(), "{}", exp);
+    }
+    assert!(parse_ast("2 + * 3").is_err());
+
+    let call = |f: Function, count: usize| Expr::new(ExprKind::Call(f, (0..count).map(|_| *num(1.0, 0..0)).collect()), 0..0);
+    assert_eq!(evaluate_ast(&call(Function::Max, 1), &scope).err().unwrap(), "Function \"max\" expects 2 argument/s");
+    assert_eq!(evaluate_ast(&call(Function::Sqrt, 4), &scope).err().unwrap(), "Function \"sqrt\" expects 1 argument/s");
+    assert_eq!(evaluate_ast(&call(Function::Len, 1), &scope).err().unwrap(), STRINGS);
+}
+#[test]
+fn deep_trees() {
+    use crate::{complex::{eval_complex, evaluate_complex}, dates::eval_dates, derivative::differentiate, format::{format_expr, FormatOptions}, interval::eval_interval, latex::format_latex, matrix::eval_matrix, simplify::simplify, strings::eval_strings, units::eval_units};
+
+    let mut scope = Scope::new();
+    scope.set("x", 1.0);
+
+    // a chain of operators isn't nested, but the tree is as deep as the chain is long
+    let (levels, terms) = (500, 2000);
+
+    for (exp, expected) in [
+        (format!("{}x{}", "-(".repeat(levels), ")".repeat(levels)), (-1f32).powi(levels as i32)),
+        (format!("{}x{}", "abs(".repeat(levels), ")".repeat(levels)), 1.0),
+        (format!("x{}", "+x".repeat(terms - 1)), terms as f32),
+        (format!("x{}", "-x".repeat(terms - 1)), 2.0 - terms as f32),
+        (format!("({})", "x+".repeat(terms - 1) + "x").repeat(2), (terms * terms) as f32),
+    ] {
+        let expr = parse_ast(&exp).unwrap();
+
+        assert_eq!(evaluate_ast(&expr, &scope).unwrap(), expected);
+        assert_eq!(evaluate_complex(&expr, &scope).unwrap().re, expected);
+        assert_eq!(eval_complex(&exp, &scope).unwrap().re, expected);
+        assert_eq!(eval_units(&exp, &scope).unwrap().value, expected);
+        assert_eq!(eval_interval(&exp, &scope).unwrap().lo, expected);
+        assert_eq!(eval_matrix(&exp, &scope), Ok(crate::matrix::Value::Number(expected)));
+        assert!(eval_dates(&exp, &scope).is_ok());
+        assert_eq!(eval_strings(&exp, &scope), Ok(crate::strings::TextValue::Number(expected)));
+        assert_eq!(crate::eval_exp_in(&exp, &scope), Ok(expected));
+
+        assert_eq!(evaluate_ast(&simplify(&expr), &scope).unwrap(), expected);
+        // derivatives are deeper than the expression they are taken of
+        let derived = differentiate(&expr, "x").unwrap();
+        assert!(evaluate_ast(&derived, &scope).is_ok());
+        assert!(!format_expr(&derived, &FormatOptions::default()).is_empty());
+        assert!(parse_ast(&format_expr(&expr, &FormatOptions::default())).unwrap().same_tree(&expr));
+        assert!(!format_latex(&expr, &FormatOptions::default()).is_empty());
+    }
+
+    let levels = crate::validation::MAX_DEPTH;
+    let nested = format!("{}x{}", "sqrt(".repeat(levels + 1), ")".repeat(levels + 1));
+    assert_eq!(parse_ast(&nested).err().unwrap(), "Brackets nested deeper than 10000 levels");
+    assert_eq!(eval_complex(&nested, &scope).err().unwrap(), "Brackets nested deeper than 10000 levels");
+    assert_eq!(evaluate_ast(&parse_ast_with_depth(&nested, levels + 1).unwrap(), &scope), Ok(1.0));
+    assert_eq!(parse_ast_with_depth("(((x)))", 2).err().unwrap(), "Brackets nested deeper than 2 levels");
+}
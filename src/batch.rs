@@ -0,0 +1,241 @@
+use std::{mem::take, ops::{Add, Div, Mul, Sub}};
+
+use crate::{bytecode::{Instruction, Program}, functions::{argument_count, Function}, token_parse::Operator};
+
+/// Rows are evaluated in chunks so the intermediate columns stay small.
+const CHUNK: usize = 4096;
+
+/// Number types that columns can be evaluated in.
+pub trait Float: Copy + PartialOrd + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> {
+    fn from_f64(n: f64) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn log10(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn min(self, n: Self) -> Self;
+    fn max(self, n: Self) -> Self;
+}
+macro_rules! impl_float {
+    ($t:ty) => {
+        impl Float for $t {
+            fn from_f64(n: f64) -> Self { n as $t }
+            fn powf(self, n: Self) -> Self { <$t>::powf(self, n) }
+            fn sqrt(self) -> Self { <$t>::sqrt(self) }
+            fn abs(self) -> Self { <$t>::abs(self) }
+            fn exp(self) -> Self { <$t>::exp(self) }
+            fn ln(self) -> Self { <$t>::ln(self) }
+            fn log10(self) -> Self { <$t>::log10(self) }
+            fn sin(self) -> Self { <$t>::sin(self) }
+            fn cos(self) -> Self { <$t>::cos(self) }
+            fn tan(self) -> Self { <$t>::tan(self) }
+            fn min(self, n: Self) -> Self { <$t>::min(self, n) }
+            fn max(self, n: Self) -> Self { <$t>::max(self, n) }
+        }
+    };
+}
+impl_float!(f32);
+impl_float!(f64);
+
+/// A value on the stack, one number for every row of the chunk.
+enum Column<'a, T> {
+    Const(T),
+    Input(&'a [T]),
+    Buffer(usize),
+}
+/// Evaluates `program` for every row, `columns` holds a column for each variable slot.
+/// Each instruction is applied to a whole chunk of rows before the next one.
+pub fn eval_batch<T: Float>(program: &Program, columns: &[&[T]], out: &mut [T]) {
+
+    for c in columns {
+        assert_eq!(c.len(), out.len(), "every column needs a value for each row");
+    }
+
+    let mut buffers: Vec<Vec<T>> = (0..program.stack_size).map(|_| vec![T::from_f64(0.0); CHUNK]).collect();
+    let mut free = Vec::with_capacity(buffers.len());
+    let mut stack = Vec::with_capacity(program.stack_size);
+    let constants: Vec<T> = program.constants.iter().map(|n| T::from_f64(*n)).collect();
+
+    for start in (0..out.len()).step_by(CHUNK) {
+
+        let end = (start + CHUNK).min(out.len());
+        let len = end - start;
+
+        stack.clear();
+        free.clear();
+        free.extend(0..buffers.len());
+
+        let mut pushed = constants.iter();
+
+        for i in program.code.iter() {
+            match i {
+                Instruction::Push(_) => stack.push(Column::Const(*pushed.next().unwrap())),
+                Instruction::Load(slot) => stack.push(Column::Input(&columns[*slot][start..end])),
+                Instruction::Operator(o) => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    stack.push(operate(o, left, right, &mut buffers, &mut free, len));
+                },
+                Instruction::Call(f) => {
+                    let right = if argument_count(f) == 2 { stack.pop() } else { None };
+                    let arg = stack.pop().unwrap();
+                    stack.push(call(f, arg, right, &mut buffers, &mut free, len));
+                },
+            }
+        }
+
+        let res = &mut out[start..end];
+
+        match stack.pop().unwrap() {
+            Column::Const(n) => res.fill(n),
+            Column::Input(c) => res.copy_from_slice(c),
+            Column::Buffer(b) => res.copy_from_slice(&buffers[b][..len]),
+        }
+    }
+}
+fn operate<'a, T: Float>(o: &Operator, left: Column<'a, T>, right: Column<'a, T>, buffers: &mut [Vec<T>], free: &mut Vec<usize>, len: usize) -> Column<'a, T> {
+    // every operator gets its own copy of the loops
+    match o {
+        Operator::Plus => operate_with(|a: T, b: T| a + b, left, right, buffers, free, len),
+        Operator::Minus => operate_with(|a: T, b: T| a - b, left, right, buffers, free, len),
+        Operator::Mul | Operator::MatMul => operate_with(|a: T, b: T| a * b, left, right, buffers, free, len),
+        Operator::Div => operate_with(|a: T, b: T| a / b, left, right, buffers, free, len),
+        Operator::Pow => operate_with(|a: T, b: T| a.powf(b), left, right, buffers, free, len),
+        // a comparison is 1 when it holds and 0 otherwise
+        Operator::Equal => operate_with(|a: T, b: T| truth(a == b), left, right, buffers, free, len),
+        Operator::NotEqual => operate_with(|a: T, b: T| truth(a != b), left, right, buffers, free, len),
+        Operator::Less => operate_with(|a: T, b: T| truth(a < b), left, right, buffers, free, len),
+        Operator::LessEqual => operate_with(|a: T, b: T| truth(a <= b), left, right, buffers, free, len),
+        Operator::Greater => operate_with(|a: T, b: T| truth(a > b), left, right, buffers, free, len),
+        Operator::GreaterEqual => operate_with(|a: T, b: T| truth(a >= b), left, right, buffers, free, len),
+    }
+}
+fn truth<T: Float>(holds: bool) -> T {
+    T::from_f64(if holds { 1.0 } else { 0.0 })
+}
+fn call<'a, T: Float>(function: &Function, arg: Column<'a, T>, right: Option<Column<'a, T>>, buffers: &mut [Vec<T>], free: &mut Vec<usize>, len: usize) -> Column<'a, T> {
+
+    // functions with one argument ignore the second operand
+    let right = right.unwrap_or(Column::Const(T::from_f64(0.0)));
+
+    match function {
+        Function::Sqrt => operate_with(|a: T, _| a.sqrt(), arg, right, buffers, free, len),
+        Function::Abs => operate_with(|a: T, _| a.abs(), arg, right, buffers, free, len),
+        Function::Exp => operate_with(|a: T, _| a.exp(), arg, right, buffers, free, len),
+        Function::Ln => operate_with(|a: T, _| a.ln(), arg, right, buffers, free, len),
+        Function::Log => operate_with(|a: T, _| a.log10(), arg, right, buffers, free, len),
+        Function::Sin => operate_with(|a: T, _| a.sin(), arg, right, buffers, free, len),
+        Function::Cos => operate_with(|a: T, _| a.cos(), arg, right, buffers, free, len),
+        Function::Tan => operate_with(|a: T, _| a.tan(), arg, right, buffers, free, len),
+        Function::Min => operate_with(|a: T, b: T| a.min(b), arg, right, buffers, free, len),
+        Function::Max => operate_with(|a: T, b: T| a.max(b), arg, right, buffers, free, len),
+        Function::Dot | Function::Transpose | Function::Det => unreachable!("matrix functions are validated"),
+        Function::Arg | Function::Conj | Function::Re | Function::Im => unreachable!("complex functions are validated"),
+        Function::Len | Function::Upper | Function::Substr | Function::Str | Function::Num => unreachable!("string functions are validated"),
+    }
+}
+fn operate_with<'a, T: Float>(f: impl Fn(T, T) -> T + Copy, left: Column<'a, T>, right: Column<'a, T>, buffers: &mut [Vec<T>], free: &mut Vec<usize>, len: usize) -> Column<'a, T> {
+    match (left, right) {
+        (Column::Const(a), Column::Const(b)) => Column::Const(f(a, b)),
+        // the result is written over the left or the right column when it is a buffer
+        (Column::Buffer(b), right) => {
+            with_operand(right, buffers, free, |buffers, right| each(&mut buffers[b][..len], right, f));
+            Column::Buffer(b)
+        },
+        (left, Column::Buffer(b)) => {
+            with_operand(left, buffers, free, |buffers, left| each(&mut buffers[b][..len], left, |x, y| f(y, x)));
+            Column::Buffer(b)
+        },
+        (left, right) => {
+            let b = materialize(left, buffers, free, len);
+            with_operand(right, buffers, free, |buffers, right| each(&mut buffers[b][..len], right, f));
+            Column::Buffer(b)
+        },
+    }
+}
+enum Operand<'a, T> {
+    Const(T),
+    Slice(&'a [T]),
+}
+/// Calls `f` with the column as an operand, a buffer is taken out while it's borrowed and freed afterwards.
+fn with_operand<T: Float>(column: Column<T>, buffers: &mut [Vec<T>], free: &mut Vec<usize>, f: impl FnOnce(&mut [Vec<T>], Operand<T>)) {
+    match column {
+        Column::Const(n) => f(buffers, Operand::Const(n)),
+        Column::Input(c) => f(buffers, Operand::Slice(c)),
+        Column::Buffer(b) => {
+            let buffer = take(&mut buffers[b]);
+            f(buffers, Operand::Slice(&buffer));
+            buffers[b] = buffer;
+            free.push(b);
+        },
+    }
+}
+/// Copies the column into a free buffer.
+fn materialize<T: Float>(column: Column<T>, buffers: &mut [Vec<T>], free: &mut Vec<usize>, len: usize) -> usize {
+    match column {
+        Column::Buffer(b) => b,
+        Column::Const(n) => {
+            let b = free.pop().unwrap();
+            buffers[b][..len].fill(n);
+            b
+        },
+        Column::Input(c) => {
+            let b = free.pop().unwrap();
+            buffers[b][..len].copy_from_slice(c);
+            b
+        },
+    }
+}
+fn each<T: Copy>(dst: &mut [T], operand: Operand<T>, f: impl Fn(T, T) -> T) {
+    match operand {
+        Operand::Const(n) => {
+            for d in dst.iter_mut() {
+                *d = f(*d, n);
+            }
+        },
+        Operand::Slice(s) => {
+            for (d, s) in dst.iter_mut().zip(s) {
+                *d = f(*d, *s);
+            }
+        },
+    }
+}
+
+#[test]
+fn batch_evaluation() {
+    use crate::compiled::CompiledExpr;
+
+    let exp = CompiledExpr::new("2x^2 - max(x, y) / (1 + y) + sqrt(4) - 3", &["x", "y"]).unwrap();
+
+    let rows = CHUNK * 2 + 17;
+    let x: Vec<f32> = (0..rows).map(|i| (i % 100) as f32 / 10.0).collect();
+    let y: Vec<f32> = (0..rows).map(|i| (i % 7) as f32).collect();
+    let mut out = vec![0.0; rows];
+
+    exp.eval_batch(&[&x, &y], &mut out);
+
+    for i in 0..rows {
+        assert_eq!(out[i], exp.eval(&[x[i], y[i]]), "row {}", i);
+    }
+
+    let x: Vec<f64> = x.iter().map(|n| *n as f64).collect();
+    let y: Vec<f64> = y.iter().map(|n| *n as f64).collect();
+    let mut out = vec![0.0; rows];
+
+    exp.eval_batch(&[&x, &y], &mut out);
+
+    assert_eq!(out[rows - 1], 2.0 * x[rows - 1].powf(2.0) - x[rows - 1].max(y[rows - 1]) / (1.0 + y[rows - 1]) + 2.0 - 3.0);
+
+    let mut out = vec![0.0f32; 3];
+    CompiledExpr::new("pi - 1", &[]).unwrap().eval_batch(&[], &mut out);
+    assert_eq!(out, [std::f32::consts::PI - 1.0; 3]);
+
+    // constants keep the precision they are written with
+    let mut out = vec![0.0f64; 2];
+    CompiledExpr::new("0.1 x + pi", &["x"]).unwrap().eval_batch(&[&[1.0, 3.0]], &mut out);
+    assert_eq!(out, [0.1 + std::f64::consts::PI, 0.1 * 3.0 + std::f64::consts::PI]);
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+
+use crate::{functions::{argument_count, call_function, function_to_string, Function}, token_parse::{apply_operator, operator_to_string, precedence, Operator, Token}};
+
+/// One step of a postfix program, values are passed on the stack of the `Vm`.
+#[derive(Clone, Copy)]
+pub enum Instruction {
+    Push(f32),
+    Load(usize),
+    Operator(Operator),
+    Call(Function),
+}
+pub struct Program {
+    pub(crate) code: Vec<Instruction>,
+    pub(crate) stack_size: usize,
+    // the value of every `Push` in order, exactly as written when known
+    pub(crate) constants: Vec<f64>,
+}
+pub fn evaluate_bytecode(tokens: &[Token]) -> f32 {
+
+    let program = compile(tokens, &[]);
+
+    Vm::new().run(&program, &[])
+}
+/// Compiles validated tokens with implicit tokens added. Every variable in `variables`
+/// is loaded from the slot with the same index, other variables must be resolved beforehand.
+pub fn compile(tokens: &[Token], variables: &[&str]) -> Program {
+
+    let mut code = Vec::with_capacity(tokens.len());
+    let mut compiler = Compiler::new();
+
+    for t in tokens {
+        compiler.push(t, variables, &mut |i| code.push(i));
+    }
+    compiler.finish(&mut |i| code.push(i));
+
+    let constants = code.iter().filter_map(|i| match i { Instruction::Push(n) => Some(*n as f64), _ => None }).collect();
+
+    Program { stack_size: stack_size(&code), constants, code }
+}
+enum Pending {
+    Operator(Operator),
+    Bracket(Option<Function>),
+}
+/// Shunting-yard state, every instruction is emitted as soon as its operands are known.
+struct Compiler {
+    stack: Vec<Pending>,
+    function: Option<Function>,
+}
+impl Compiler {
+    fn new() -> Self {
+        Compiler { stack: Vec::new(), function: None }
+    }
+    fn push(&mut self, t: &Token, variables: &[&str], emit: &mut impl FnMut(Instruction)) {
+        match t {
+            Token::Number(n) => emit(Instruction::Push(*n)),
+            Token::Variable(name) => {
+                let slot = variables.iter().position(|v| v == name).expect("variables are resolved before evaluation");
+                emit(Instruction::Load(slot));
+            },
+            Token::Function(f) => self.function = Some(*f),
+            Token::List(_) => unreachable!("lists are validated"),
+            Token::Str(_) => unreachable!("strings are validated"),
+            Token::Operator(o) => {
+                while let Some(Pending::Operator(top)) = self.stack.last() {
+                    // exponentiation is right associative
+                    if precedence(top) < precedence(o) || (precedence(top) == precedence(o) && *o == Operator::Pow) {
+                        break;
+                    }
+                    emit(Instruction::Operator(*top));
+                    self.stack.pop();
+                }
+                self.stack.push(Pending::Operator(*o));
+            },
+            Token::Comma => {
+                while let Some(Pending::Operator(top)) = self.stack.last() {
+                    emit(Instruction::Operator(*top));
+                    self.stack.pop();
+                }
+            },
+            Token::Bracket(b) => {
+                if *b {
+                    self.stack.push(Pending::Bracket(self.function.take()));
+                    return;
+                }
+                loop {
+                    match self.stack.pop() {
+                        Some(Pending::Operator(top)) => emit(Instruction::Operator(top)),
+                        Some(Pending::Bracket(f)) => {
+                            if let Some(f) = f { emit(Instruction::Call(f)); }
+                            break;
+                        },
+                        None => unreachable!("brackets are validated"),
+                    }
+                }
+            },
+        }
+    }
+    fn finish(&mut self, emit: &mut impl FnMut(Instruction)) {
+        while let Some(Pending::Operator(top)) = self.stack.pop() {
+            emit(Instruction::Operator(top));
+        }
+    }
+}
+/// Evaluates tokens as they come in without keeping them, only the pending operators
+/// and their operands are stored. Takes the same tokens as `evaluate_bytecode`.
+pub struct StreamEvaluator {
+    compiler: Compiler,
+    vm: Vm,
+}
+impl StreamEvaluator {
+    pub fn new() -> Self {
+        StreamEvaluator { compiler: Compiler::new(), vm: Vm::new() }
+    }
+    pub fn push(&mut self, t: &Token) {
+        let vm = &mut self.vm;
+        self.compiler.push(t, &[], &mut |i| vm.execute(&i, &[]));
+    }
+    pub fn finish(mut self) -> f32 {
+        let vm = &mut self.vm;
+        self.compiler.finish(&mut |i| vm.execute(&i, &[]));
+
+        self.vm.stack.pop().unwrap()
+    }
+    /// Like `push`, `check` is called with every instruction and the stack before the instruction runs.
+    /// Nothing more runs after `check` fails.
+    pub fn push_checked<E>(&mut self, t: &Token, check: &mut impl FnMut(&Instruction, &[f32]) -> Result<(), E>) -> Result<(), E> {
+
+        let vm = &mut self.vm;
+        let mut res = Ok(());
+
+        self.compiler.push(t, &[], &mut |i| run_checked(vm, &i, check, &mut res));
+        res
+    }
+    /// Like `finish`, with the instructions that are left checked as in `push_checked`.
+    pub fn finish_checked<E>(mut self, check: &mut impl FnMut(&Instruction, &[f32]) -> Result<(), E>) -> Result<f32, E> {
+
+        let vm = &mut self.vm;
+        let mut res = Ok(());
+
+        self.compiler.finish(&mut |i| run_checked(vm, &i, check, &mut res));
+        res?;
+
+        Ok(self.vm.stack.pop().unwrap())
+    }
+}
+fn run_checked<E>(vm: &mut Vm, i: &Instruction, check: &mut impl FnMut(&Instruction, &[f32]) -> Result<(), E>, res: &mut Result<(), E>) {
+    if res.is_ok() {
+        *res = check(i, &vm.stack);
+
+        if res.is_ok() { vm.execute(i, &[]); }
+    }
+}
+impl Default for StreamEvaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+fn stack_size(code: &[Instruction]) -> usize {
+
+    let mut size = 0;
+    let mut max = 0;
+
+    for i in code {
+        match i {
+            Instruction::Push(_) | Instruction::Load(_) => size += 1,
+            Instruction::Operator(_) => size -= 1,
+            Instruction::Call(f) => size = size + 1 - argument_count(f),
+        }
+        max = max.max(size);
+    }
+    max
+}
+/// Prints the instructions in postfix order after the size of the stack they need,
+/// `$0` loads the first variable and `max()` calls a function.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bytecode (stack {}):", self.stack_size)?;
+        for i in self.code.iter() {
+            match i {
+                Instruction::Push(n) => write!(f, " {}", n)?,
+                Instruction::Load(slot) => write!(f, " ${}", slot)?,
+                Instruction::Operator(o) => write!(f, " {}", operator_to_string(o))?,
+                Instruction::Call(function) => write!(f, " {}()", function_to_string(function))?,
+            }
+        }
+        Ok(())
+    }
+}
+/// Runs programs, the stack is kept between runs so only the first run of a program allocates.
+pub struct Vm {
+    stack: Vec<f32>,
+}
+impl Vm {
+    pub fn new() -> Self {
+        Vm { stack: Vec::new() }
+    }
+    pub fn run(&mut self, program: &Program, vars: &[f32]) -> f32 {
+
+        self.stack.clear();
+        self.stack.reserve(program.stack_size);
+
+        for i in program.code.iter() {
+            self.execute(i, vars);
+        }
+        self.stack.pop().unwrap()
+    }
+    fn execute(&mut self, i: &Instruction, vars: &[f32]) {
+        match i {
+            Instruction::Push(n) => self.stack.push(*n),
+            Instruction::Load(slot) => self.stack.push(vars[*slot]),
+            Instruction::Operator(o) => {
+                let right = self.stack.pop().unwrap();
+                let left = self.stack.last_mut().unwrap();
+                *left = apply_operator(o, *left, right);
+            },
+            Instruction::Call(f) => {
+                let start = self.stack.len() - argument_count(f);
+                let res = call_function(f, &self.stack[start..]);
+                self.stack.truncate(start);
+                self.stack.push(res);
+            },
+        }
+    }
+}
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn bytecode_evaluation() {
+    use crate::{prepare_tokens, script::{resolve_variables, Scope}};
+
+    let mut scope = Scope::new();
+    scope.set("x", 3.0);
+
+    for exp in ["1 +2* 3 +4", "2 / 4 / 2", "2^3^2", "-2^2 * 3", "(1 + 2 * 3) * 4 (1 + 2 + 3) * 5", "2max(1, -x) + sqrt(16)", "min(2, max(3x, 1)) ^ 2"] {
+        let mut tokens = prepare_tokens(exp).unwrap();
+        resolve_variables(&mut tokens, &scope).unwrap();
+
+        assert_eq!(evaluate_bytecode(&tokens), crate::linear_eval::linear_eval(tokens), "{}", exp);
+    }
+
+    let tokens = prepare_tokens("x * y - x").unwrap();
+    let program = compile(&tokens, &["x", "y"]);
+    let mut vm = Vm::new();
+
+    assert_eq!(vm.run(&program, &[2.0, 5.0]), 8.0);
+    assert_eq!(vm.run(&program, &[-1.0, 3.0]), -2.0);
+
+    assert_eq!(program.to_string(), "Bytecode (stack 2): $0 $1 * $0 -");
+    assert_eq!(compile(&prepare_tokens("max(x, 2)").unwrap(), &["x"]).to_string(), "Bytecode (stack 2): $0 2 max()");
+}
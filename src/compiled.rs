@@ -0,0 +1,93 @@
+use std::sync::Mutex;
+
+use crate::{batch::{eval_batch, Float}, bytecode::{compile, Program}, script::Scope, token_parse::{parse_exp_spans, Token}, token_tree::{create_tree, Scratch, TokenTree}, validation::{add_implicit_tokens_spans, validate_tokens}};
+
+/// An expression that is parsed and validated once and can then be evaluated
+/// any number of times with different variable values.
+pub struct CompiledExpr {
+    tree: TokenTree,
+    program: Program,
+    variables: Vec<String>,
+    // the stacks `eval` evaluates big expressions with, kept between calls
+    scratch: Mutex<Scratch>,
+}
+impl CompiledExpr {
+    /// Compiles `input` with `variables` as its inputs, in the order their values are passed to `eval`.
+    /// Any other variable has to be a built-in constant.
+    pub fn new(input: &str, variables: &[&str]) -> Result<Self, String> {
+
+        let (mut tokens, mut spans) = parse_exp_spans(input)?;
+        validate_tokens(&tokens)?;
+        add_implicit_tokens_spans(&mut tokens, &mut spans);
+
+        let constants = Scope::new();
+        // every number in f64 for `eval_batch`, read again from the input so no precision is lost
+        let mut exact = Vec::new();
+
+        for (t, span) in tokens.iter_mut().zip(spans) {
+            match t {
+                Token::Number(n) => exact.push(input[span].parse().unwrap_or(*n as f64)),
+                Token::Variable(name) if !variables.contains(&name.as_str()) => {
+                    let n = constants.get(name).ok_or_else(|| format!("Unknown variable: \"{}\"", name))?;
+
+                    exact.push(match name.as_str() {
+                        "pi" => std::f64::consts::PI,
+                        "e" => std::f64::consts::E,
+                        _ => n as f64,
+                    });
+                    *t = Token::Number(n);
+                },
+                _ => {},
+            }
+        }
+
+        let mut program = compile(&tokens, variables);
+        program.constants = exact;
+
+        Ok(CompiledExpr { 
+            tree: create_tree(&tokens, variables), 
+            program,
+            variables: variables.iter().map(|v| v.to_string()).collect(),
+            scratch: Mutex::new(Scratch::default()),
+        })
+    }
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+    /// Evaluates the expression, `vars` holds a value for each of `variables()` in the same order.
+    /// Panics if the number of values is different.
+    /// 
+    /// Expressions of about 10,000 tokens or more are evaluated without recursion. The memory for that
+    /// is kept between calls, only a call while another thread evaluates the same expression allocates.
+    pub fn eval(&self, vars: &[f32]) -> f32 {
+        match self.scratch.try_lock() {
+            Ok(mut scratch) => self.eval_with(vars, &mut scratch),
+            Err(_) => self.eval_with(vars, &mut Scratch::default()),
+        }
+    }
+    /// Like `eval`, big expressions keep their intermediate values in `scratch`, one for every thread.
+    pub fn eval_with(&self, vars: &[f32], scratch: &mut Scratch) -> f32 {
+        assert_eq!(vars.len(), self.variables.len(), "expected a value for each variable");
+
+        self.tree.evaluate(vars, scratch)
+    }
+    /// Evaluates the expression for every row, `columns` holds a column of values for each of `variables()`.
+    /// Panics if the number of columns is different, or a column doesn't have as many rows as `out`.
+    pub fn eval_batch<T: Float>(&self, columns: &[&[T]], out: &mut [T]) {
+        assert_eq!(columns.len(), self.variables.len(), "expected a column for each variable");
+
+        eval_batch(&self.program, columns, out);
+    }
+}
+
+#[test]
+fn compiled_evaluation() {
+    let exp = CompiledExpr::new("2x^2 - (x - y) / 2 + pi", &["x", "y"]).unwrap();
+
+    assert_eq!(exp.variables(), ["x", "y"]);
+    assert_eq!(exp.eval(&[3.0, 1.0]), 18.0 - 1.0 + std::f32::consts::PI);
+    assert_eq!(exp.eval(&[0.0, 4.0]), 2.0 + std::f32::consts::PI);
+
+    assert_eq!(CompiledExpr::new("(z)", &[]).err().unwrap(), "Unknown variable: \"z\"");
+    assert_eq!(CompiledExpr::new("((2))", &[]).unwrap().eval(&[]), 2.0);
+}
@@ -0,0 +1,282 @@
+use std::{fmt, ops::{Add, Div, Mul, Neg, Sub}};
+
+use crate::{ast::{ast_from_complex_tokens, check_call, Expr, ExprKind, UnaryOp}, functions::Function, script::Scope, token_parse::{is_comparison, parse_exp_spans, Operator, Span, Token}, validation::{Validator, COMPARISONS, LISTS, STRINGS}};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+impl Complex {
+    pub const I: Complex = Complex { re: 0.0, im: 1.0 };
+
+    pub fn new(re: f32, im: f32) -> Self {
+        Complex { re, im }
+    }
+    pub fn from_polar(r: f32, angle: f32) -> Self {
+        Complex::new(r * angle.cos(), r * angle.sin())
+    }
+    pub fn abs(self) -> f32 {
+        self.re.hypot(self.im)
+    }
+    /// The angle in `(-pi, pi]`.
+    pub fn arg(self) -> f32 {
+        self.im.atan2(self.re)
+    }
+    pub fn conj(self) -> Self {
+        Complex::new(self.re, -self.im)
+    }
+    pub fn exp(self) -> Self {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+    pub fn ln(self) -> Self {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+    /// The root with a positive real part, exact for real numbers.
+    pub fn sqrt(self) -> Self {
+        match self.im {
+            0.0 if self.re >= 0.0 => Complex::new(self.re.sqrt(), 0.0),
+            0.0 => Complex::new(0.0, (-self.re).sqrt()),
+            _ => Complex::from_polar(self.abs().sqrt(), self.arg() / 2.0),
+        }
+    }
+    /// Integer powers are multiplied out, so `(1 + i)^2` is exactly `2i`.
+    pub fn pow(self, n: Complex) -> Self {
+        if n.im == 0.0 && n.re.fract() == 0.0 && n.re.abs() <= 1024.0 {
+            let mut res = Complex::new(1.0, 0.0);
+            let mut base = self;
+            let mut e = n.re.abs() as u32;
+
+            while e > 0 {
+                if e & 1 == 1 {
+                    res = res * base;
+                }
+                base = base * base;
+                e >>= 1;
+            }
+            return if n.re < 0.0 { Complex::new(1.0, 0.0) / res } else { res };
+        }
+        if self == Complex::default() {
+            return self;
+        }
+        (n * self.ln()).exp()
+    }
+    pub fn sin(self) -> Self {
+        Complex::new(self.re.sin() * self.im.cosh(), self.re.cos() * self.im.sinh())
+    }
+    pub fn cos(self) -> Self {
+        Complex::new(self.re.cos() * self.im.cosh(), -self.re.sin() * self.im.sinh())
+    }
+    pub fn tan(self) -> Self {
+        self.sin() / self.cos()
+    }
+}
+impl From<f32> for Complex {
+    fn from(re: f32) -> Self {
+        Complex::new(re, 0.0)
+    }
+}
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, c: Complex) -> Complex {
+        Complex::new(self.re + c.re, self.im + c.im)
+    }
+}
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, c: Complex) -> Complex {
+        Complex::new(self.re - c.re, self.im - c.im)
+    }
+}
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, c: Complex) -> Complex {
+        Complex::new(self.re * c.re - self.im * c.im, self.re * c.im + self.im * c.re)
+    }
+}
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, c: Complex) -> Complex {
+        let d = c.re * c.re + c.im * c.im;
+        Complex::new((self.re * c.re + self.im * c.im) / d, (self.im * c.re - self.re * c.im) / d)
+    }
+}
+impl Neg for Complex {
+    type Output = Complex;
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+/// Prints `a + bi`, leaving out a part that is zero.
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.im == 0.0 {
+            write!(f, "{}", self.re)
+        }
+        else if self.re == 0.0 {
+            write!(f, "{}i", self.im)
+        }
+        else {
+            let sign = if self.im.is_sign_negative() { '-' } else { '+' };
+            write!(f, "{} {} {}i", self.re, sign, self.im.abs())
+        }
+    }
+}
+/// Prints `r*e^(ai)` with the absolute value `r` and the angle `a`, which `eval_complex` reads back.
+pub fn format_polar(z: Complex) -> String {
+    format!("{}*e^({}i)", z.abs(), z.arg())
+}
+/// Evaluates the input with complex numbers. `i` and `j` are the imaginary unit, written right
+/// after a number they make an imaginary literal, so `1/2i` is `1/(2i)`. Variables of the scope are real.
+pub fn eval_complex(input: &str, scope: &Scope) -> Result<Complex, String> {
+    evaluate_complex(&parse_complex_ast(input)?, scope)
+}
+/// Like `parse_ast`, imaginary literals like `4i` are parsed as bracketed products.
+pub fn parse_complex_ast(input: &str) -> Result<Expr, String> {
+
+    let (tokens, spans) = parse_exp_spans(input)?;
+
+    let mut literals = (Vec::with_capacity(tokens.len()), Vec::with_capacity(spans.len()));
+    let mut tokens = tokens.into_iter().zip(spans).peekable();
+
+    while let Some((t, span)) = tokens.next() {
+        let imaginary = matches!(&t, Token::Number(_)) && matches!(tokens.peek(), Some((Token::Variable(name), s)) if is_unit(name) && s.start == span.end);
+
+        if imaginary {
+            let (unit, unit_span) = tokens.next().unwrap();
+            let (start, end) = (span.start, unit_span.end);
+
+            let literal: [(Token, Span); 5] = [
+                (Token::Bracket(true), start..start),
+                (t, span.clone()),
+                (Token::Operator(Operator::Mul), span.end..span.end),
+                (unit, unit_span),
+                (Token::Bracket(false), end..end),
+            ];
+            for (t, span) in literal {
+                literals.0.push(t);
+                literals.1.push(span);
+            }
+        }
+        else {
+            literals.0.push(t);
+            literals.1.push(span);
+        }
+    }
+
+    ast_from_complex_tokens(literals)
+}
+fn is_unit(name: &str) -> bool {
+    name == "i" || name == "j"
+}
+pub fn evaluate_complex(expr: &Expr, scope: &Scope) -> Result<Complex, String> {
+    expr.try_fold(|e, values: Vec<Complex>| match &e.kind {
+        ExprKind::Num(n) => Ok(Complex::from(*n)),
+        ExprKind::Var(name) if is_unit(name) => Ok(Complex::I),
+        ExprKind::Var(name) => scope.get(name).map(Complex::from).ok_or_else(|| format!("Unknown variable: \"{}\"", name)),
+        ExprKind::Unary(UnaryOp::Neg, _) => Ok(-values[0]),
+        ExprKind::Binary(Operator::MatMul, ..) => Err(LISTS.to_string()),
+        ExprKind::Binary(o, ..) if is_comparison(o) => Err(COMPARISONS.to_string()),
+        ExprKind::Binary(o, ..) => {
+            let (a, b) = (values[0], values[1]);
+
+            Ok(match o {
+                Operator::Plus => a + b,
+                Operator::Minus => a - b,
+                Operator::Mul => a * b,
+                Operator::Div => a / b,
+                Operator::Pow => a.pow(b),
+                Operator::MatMul => unreachable!("@ is checked"),
+                _ => unreachable!("comparisons are checked"),
+            })
+        },
+        ExprKind::Call(f, args) => {
+            check_call(f, args, &Validator::with_complex())?;
+
+            let z = values[0];
+
+            Ok(match f {
+                Function::Sqrt => z.sqrt(),
+                Function::Abs => Complex::from(z.abs()),
+                Function::Exp => z.exp(),
+                Function::Ln => z.ln(),
+                Function::Log => z.ln() / Complex::from(10f32.ln()),
+                Function::Sin => z.sin(),
+                Function::Cos => z.cos(),
+                Function::Tan => z.tan(),
+                Function::Min | Function::Max => {
+                    let w = values[1];
+
+                    if z.im != 0.0 || w.im != 0.0 {
+                        return Err("min and max need real arguments".to_string());
+                    }
+                    Complex::from(if *f == Function::Min { z.re.min(w.re) } else { z.re.max(w.re) })
+                },
+                Function::Arg => Complex::from(z.arg()),
+                Function::Conj => z.conj(),
+                Function::Re => Complex::from(z.re),
+                Function::Im => Complex::from(z.im),
+                Function::Dot | Function::Transpose | Function::Det => unreachable!("matrix functions are checked"),
+                Function::Len | Function::Upper | Function::Substr | Function::Str | Function::Num => unreachable!("string functions are checked"),
+            })
+        },
+        ExprKind::List(_) => Err(LISTS.to_string()),
+        ExprKind::Str(_) => Err(STRINGS.to_string()),
+    })
+}
+
+#[test]
+fn complex_arithmetic() {
+    use std::f32::consts::PI;
+
+    let eval = |exp: &str| eval_complex(exp, &Scope::new()).unwrap_or_else(|err| panic!("{}: {}", exp, err));
+
+    for (exp, re, im) in [
+        ("(3 + 4i) * (1 - 2i)", 11.0, -2.0),
+        ("sqrt(-1)", 0.0, 1.0),
+        ("abs(3 + 4j)", 5.0, 0.0),
+        ("arg(i) * 2", PI, 0.0),
+        ("conj(3 + 4i) + re(2 - i) + im(2 - i)", 4.0, -4.0),
+        ("(1 + i)^2", 0.0, 2.0),
+        ("1/2i", 0.0, -0.5),
+        ("1/2 i", 0.0, 0.5),
+        ("2.5e1i - i^3 + 2i*i", -2.0, 26.0),
+        ("(2 - i)^(-1) - 1/(2 - i)", 0.0, 0.0),
+    ] {
+        assert_eq!(eval(exp), Complex::new(re, im), "{}", exp);
+    }
+
+    for (exp, z) in [
+        ("e^(pi*i)", Complex::from(-1.0)),
+        ("2^3i", Complex::from_polar(1.0, 3.0 * 2f32.ln())),
+        ("ln(-1) + exp(2 ln(i))", Complex::new(-1.0, PI)),
+        ("sin(i)^2 + cos(i)^2", Complex::from(1.0)),
+        ("tan(1 + i) * cos(1 + i) - sin(1 + i)", Complex::default()),
+        ("log(100i)", Complex::new(2.0, PI / 2.0 / 10f32.ln())),
+    ] {
+        assert!((eval(exp) - z).abs() < 1e-5, "{}: {} is not {}", exp, eval(exp), z);
+    }
+
+    let mut scope = Scope::new();
+    scope.set("r", 2.0);
+    assert_eq!(eval_complex("r*i + r", &scope), Ok(Complex::new(2.0, 2.0)));
+    assert_eq!(eval_complex("min(i, 1)", &scope), Err("min and max need real arguments".to_string()));
+
+    assert_eq!(Complex::new(11.0, -2.0).to_string(), "11 - 2i");
+    assert_eq!(Complex::new(0.0, -0.5).to_string(), "-0.5i");
+    assert_eq!(Complex::new(5.0, 0.0).to_string(), "5");
+
+    let z = Complex::new(-3.0, 4.0);
+    assert!((eval(&format_polar(z)) - z).abs() < 1e-5, "{}", format_polar(z));
+    assert!((eval(&z.to_string()) - z).abs() == 0.0);
+
+    // only the complex evaluator knows the parts of a complex number
+    let error = Err(crate::validation::COMPLEX.to_string());
+    assert_eq!(crate::eval_exp("re(2)"), error);
+    assert_eq!(crate::units::eval_units("im(2 m)", &scope).map(|m| m.value), error);
+    assert_eq!(crate::interval::eval_interval("arg(-2)", &scope).map(|i| i.lo), error);
+
+    let expr = parse_complex_ast("conj(3)").unwrap();
+    assert_eq!(crate::ast::evaluate_ast(&expr, &scope), error);
+    assert!(crate::simplify::simplify(&expr).same_tree(&expr));
+}
@@ -0,0 +1,375 @@
+use std::{fmt, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{ast::{ast_from_tokens, check_call, Expr, ExprKind, UnaryOp}, functions::{call_function, function_to_string, Function}, format::is_implicit_zero, script::Scope, token_parse::{is_comparison, operator_to_string, parse_exp_spans, Operator, Span, Token}, validation::{Validator, COMPARISONS, LISTS, STRINGS}};
+
+const DAY: f64 = 86400.0;
+
+/// A number, a point in time in seconds since 1970-01-01 00:00 UTC, or a duration in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeValue {
+    Number(f64),
+    Date(f64),
+    Duration(f64),
+}
+impl TimeValue {
+    fn kind(&self) -> &'static str {
+        match self {
+            TimeValue::Number(_) => "a number",
+            TimeValue::Date(_) => "a date",
+            TimeValue::Duration(_) => "a duration",
+        }
+    }
+}
+/// Prints dates as `2026-10-18`, with the time of day if it isn't midnight, and durations
+/// in the largest of days, hours, minutes and seconds they fill, like `1.5 h`.
+impl fmt::Display for TimeValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeValue::Number(n) => write!(f, "{}", n as f32),
+            TimeValue::Date(t) => {
+                let days = (t / DAY).floor();
+                let (year, month, day) = civil_from_days(days as i64);
+                write!(f, "{:04}-{:02}-{:02}", year, month, day)?;
+
+                let seconds = (t - days * DAY).floor() as i64;
+                match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+                    (0, 0, 0) => Ok(()),
+                    (h, m, 0) => write!(f, " {:02}:{:02}", h, m),
+                    (h, m, s) => write!(f, " {:02}:{:02}:{:02}", h, m, s),
+                }
+            },
+            TimeValue::Duration(d) => {
+                let (name, seconds) = [("days", DAY), ("h", 3600.0), ("min", 60.0)].into_iter()
+                    .find(|(_, unit)| d.abs() >= *unit)
+                    .unwrap_or(("s", 1.0));
+
+                let n = (d / seconds) as f32;
+                write!(f, "{} {}", n, if n.abs() == 1.0 && name == "days" { "day" } else { name })
+            },
+        }
+    }
+}
+/// The length in seconds of a duration unit like `days` or `h`.
+pub fn duration_unit(name: &str) -> Option<f64> {
+    Some(match name {
+        "ms" => 1e-3,
+        "s" | "sec" | "second" | "seconds" => 1.0,
+        "min" | "minute" | "minutes" => 60.0,
+        "h" | "hour" | "hours" => 3600.0,
+        "d" | "day" | "days" => DAY,
+        "week" | "weeks" => 7.0 * DAY,
+        _ => return None,
+    })
+}
+/// Evaluates the input with dates and durations. A date is written as `date("2026-10-18")` or
+/// `date("2026-10-18 09:30")` in UTC, `now()` is the current time, and a duration is a number
+/// with a unit like `3 days`, `2 h` or `30 min`. Ends with `in` or `to` and a unit, a duration
+/// is converted to a number of that unit.
+pub fn eval_dates(input: &str, scope: &Scope) -> Result<TimeValue, String> {
+    eval_dates_with_clock(input, scope, &system_time)
+}
+/// Like `eval_dates`, `now()` is the time `clock` returns in seconds since 1970-01-01 00:00 UTC.
+pub fn eval_dates_with_clock(input: &str, scope: &Scope, clock: &dyn Fn() -> f64) -> Result<TimeValue, String> {
+
+    let mut literals = Vec::new();
+    let (mut tokens, mut spans) = date_tokens(input, clock, &mut literals)?;
+
+    // the conversion binds looser than everything else
+    let mut depth = 0;
+    let to = tokens.iter().position(|t| {
+        match t {
+            Token::Bracket(open) => depth += if *open { 1 } else { -1 },
+            Token::Variable(name) => return depth == 0 && (name == "to" || name == "in"),
+            _ => {},
+        }
+        false
+    });
+
+    let target = match to {
+        Some(to) => {
+            let target = input[spans[to].end..].trim();
+            let seconds = duration_unit(target).ok_or_else(|| format!("Unknown duration unit: \"{}\"", target))?;
+
+            tokens.truncate(to);
+            spans.truncate(to);
+            Some(seconds)
+        },
+        None => None,
+    };
+
+    let value = evaluate_time(&ast_from_tokens(group_durations(tokens, spans))?, scope, &literals)?;
+
+    match (value, target) {
+        (TimeValue::Duration(d), Some(unit)) => Ok(TimeValue::Number(d / unit)),
+        (value, Some(_)) => Err(format!("Only durations can be converted, not {}", value.kind())),
+        (value, None) => Ok(value),
+    }
+}
+fn system_time() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+/// Tokenizes the input with every `date("...")` and `now()` moved to `literals` and replaced
+/// by a variable named by its index in brackets, a name that can't be written.
+fn date_tokens(input: &str, clock: &dyn Fn() -> f64, literals: &mut Vec<f64>) -> Result<(Vec<Token>, Vec<Span>), String> {
+
+    let (tokens, spans) = parse_exp_spans(input)?;
+
+    let mut res = (Vec::with_capacity(tokens.len()), Vec::with_capacity(spans.len()));
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let name = match &tokens[i] {
+            Token::Variable(name) if (name == "date" || name == "now") && matches!(tokens.get(i + 1), Some(Token::Bracket(true))) => name.as_str(),
+            t => {
+                res.0.push(t.clone());
+                res.1.push(spans[i].clone());
+                i += 1;
+                continue;
+            },
+        };
+
+        // the index of the closing bracket
+        let (time, close) = match (name, tokens.get(i + 2)) {
+            ("now", _) => (clock(), i + 2),
+            (_, Some(Token::Str(text))) => (parse_date(text)?, i + 3),
+            _ => return Err("Expected a date in quotes, like date(\"2026-10-18\")".to_string()),
+        };
+
+        if !matches!(tokens.get(close), Some(Token::Bracket(false))) {
+            return Err(format!("Expected \")\" after the argument of {}", name));
+        }
+
+        res.0.push(Token::Variable(format!("[{}]", literals.len())));
+        res.1.push(spans[i].start..spans[close].end);
+        literals.push(time);
+
+        i = close + 1;
+    }
+
+    Ok(res)
+}
+/// Reads `2026-10-18`, optionally followed by a time as `09:30` or `09:30:15` after a space or `T`.
+fn parse_date(text: &str) -> Result<f64, String> {
+
+    let invalid = || format!("Invalid date: \"{}\", expected a date like \"2026-10-18\" or \"2026-10-18 09:30\"", text);
+    let number = |s: &str, digits: usize| if s.len() == digits && s.bytes().all(|c| c.is_ascii_digit()) { s.parse::<i64>().ok() } else { None };
+
+    let (date, time) = match text.trim().split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time.trim_start().trim_end_matches('Z'))),
+        None => (text.trim(), None),
+    };
+
+    let mut parts = date.split('-');
+    let (Some(year), Some(month), Some(day), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    let (year, month, day) = (number(year, 4).ok_or_else(invalid)?, number(month, 2).ok_or_else(invalid)?, number(day, 2).ok_or_else(invalid)?);
+
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0;
+
+    if let Some(time) = time {
+        let parts: Vec<&str> = time.split(':').collect();
+
+        if !(2..=3).contains(&parts.len()) {
+            return Err(invalid());
+        }
+        // hours, minutes and seconds
+        for (part, (limit, unit)) in parts.iter().zip([(24, 3600), (60, 60), (60, 1)]) {
+            seconds += number(part, 2).filter(|n| *n < limit).ok_or_else(invalid)? * unit;
+        }
+    }
+
+    Ok(days_from_civil(year, month, day) as f64 * DAY + seconds as f64)
+}
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+/// The days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+
+    // years start in March, so the leap day is the last day of a year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+/// Groups a number with the duration unit after it, so `6 h / 2 h` is `(6 * h) / (2 * h)`.
+fn group_durations(tokens: Vec<Token>, spans: Vec<Span>) -> (Vec<Token>, Vec<Span>) {
+
+    // `min` is minutes unless it is called
+    let tokens: Vec<Token> = tokens.iter().enumerate().map(|(i, t)| match t {
+        Token::Function(Function::Min) if !matches!(tokens.get(i + 1), Some(Token::Bracket(true))) => Token::Variable("min".to_string()),
+        t => t.clone(),
+    }).collect();
+
+    let mut grouped = (Vec::with_capacity(tokens.len()), Vec::with_capacity(spans.len()));
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let unit_follows = matches!(tokens.get(i + 1), Some(Token::Variable(name)) if duration_unit(name).is_some());
+
+        if matches!(tokens[i], Token::Number(_)) && unit_follows {
+            let (start, end) = (spans[i].start, spans[i + 1].end);
+
+            grouped.0.extend([Token::Bracket(true), tokens[i].clone(), Token::Operator(Operator::Mul), tokens[i + 1].clone(), Token::Bracket(false)]);
+            grouped.1.extend([start..start, spans[i].clone(), spans[i].end..spans[i].end, spans[i + 1].clone(), end..end]);
+            i += 2;
+        }
+        else {
+            grouped.0.push(tokens[i].clone());
+            grouped.1.push(spans[i].clone());
+            i += 1;
+        }
+    }
+    grouped
+}
+fn negate(value: TimeValue) -> Result<TimeValue, String> {
+    use TimeValue::{Date, Duration, Number};
+
+    match value {
+        Number(n) => Ok(Number(-n)),
+        Duration(d) => Ok(Duration(-d)),
+        Date(_) => Err("A date can't be negated".to_string()),
+    }
+}
+fn evaluate_time(expr: &Expr, scope: &Scope, literals: &[f64]) -> Result<TimeValue, String> {
+    use TimeValue::{Date, Duration, Number};
+
+    expr.try_fold(|e, values: Vec<TimeValue>| match &e.kind {
+        ExprKind::Num(n) => Ok(Number(*n as f64)),
+        ExprKind::Var(name) if name.starts_with('[') => Ok(Date(literals[name[1..name.len() - 1].parse::<usize>().unwrap()])),
+        ExprKind::Var(name) => {
+            match (scope.get(name), duration_unit(name)) {
+                (Some(n), _) => Ok(Number(n as f64)),
+                (None, Some(seconds)) => Ok(Duration(seconds)),
+                (None, None) => Err(format!("Unknown variable or unit: \"{}\"", name)),
+            }
+        },
+        ExprKind::Unary(UnaryOp::Neg, _) => negate(values[0]),
+        // a leading minus is parsed as a subtraction from zero
+        ExprKind::Binary(Operator::Minus, zero, _) if is_implicit_zero(zero) => negate(values[1]),
+        ExprKind::Binary(Operator::MatMul, ..) => Err(LISTS.to_string()),
+        ExprKind::Binary(o, ..) if is_comparison(o) => Err(COMPARISONS.to_string()),
+        ExprKind::Binary(o, ..) => {
+            let (a, b) = (values[0], values[1]);
+
+            Ok(match (o, a, b) {
+                (Operator::Plus, Number(x), Number(y)) => Number(x + y),
+                (Operator::Minus, Number(x), Number(y)) => Number(x - y),
+                (Operator::Mul, Number(x), Number(y)) => Number(x * y),
+                (Operator::Div, Number(x), Number(y)) => Number(x / y),
+                (Operator::Pow, Number(x), Number(y)) => Number(x.powf(y)),
+
+                (Operator::Plus, Date(t), Duration(d)) | (Operator::Plus, Duration(d), Date(t)) => Date(t + d),
+                (Operator::Minus, Date(t), Duration(d)) => Date(t - d),
+                (Operator::Minus, Date(t), Date(u)) => Duration(t - u),
+
+                (Operator::Plus, Duration(x), Duration(y)) => Duration(x + y),
+                (Operator::Minus, Duration(x), Duration(y)) => Duration(x - y),
+                (Operator::Mul, Duration(d), Number(n)) | (Operator::Mul, Number(n), Duration(d)) => Duration(d * n),
+                (Operator::Div, Duration(d), Number(n)) => Duration(d / n),
+                (Operator::Div, Duration(x), Duration(y)) => Number(x / y),
+
+                (o, a, b) => return Err(format!("\"{}\" doesn't work on {} and {}", operator_to_string(o), a.kind(), b.kind())),
+            })
+        },
+        ExprKind::Call(f, args) => {
+            check_call(f, args, &Validator::new())?;
+
+            let a = values[0];
+
+            match (f, a) {
+                (Function::Min | Function::Max, a) => {
+                    let b = values[1];
+                    let min = *f == Function::Min;
+
+                    match (a, b) {
+                        (Number(x), Number(y)) => Ok(Number(if min { x.min(y) } else { x.max(y) })),
+                        (Date(x), Date(y)) => Ok(Date(if min { x.min(y) } else { x.max(y) })),
+                        (Duration(x), Duration(y)) => Ok(Duration(if min { x.min(y) } else { x.max(y) })),
+                        (a, b) => Err(format!("{} needs two values of the same kind, not {} and {}", function_to_string(f), a.kind(), b.kind())),
+                    }
+                },
+                (Function::Abs, Duration(d)) => Ok(Duration(d.abs())),
+                (f, Number(x)) => Ok(Number(call_function(f, &[x as f32]) as f64)),
+                (f, a) => Err(format!("{} needs a number, not {}", function_to_string(f), a.kind())),
+            }
+        },
+        ExprKind::List(_) => Err(LISTS.to_string()),
+        ExprKind::Str(_) => Err(STRINGS.to_string()),
+    })
+}
+
+#[test]
+fn dates_and_durations() {
+    let mut scope = Scope::new();
+    scope.set("n", 4.0);
+
+    // 2026-10-19 12:00 UTC
+    let clock = || 1_792_411_200.0;
+    let eval = |exp: &str| eval_dates_with_clock(exp, &scope, &clock).unwrap_or_else(|err| panic!("{}: {}", exp, err));
+
+    for (exp, s) in [
+        ("date(\"2026-10-18\") + 3 days", "2026-10-21"),
+        ("date(\"2024-02-28\") + 1 day", "2024-02-29"),
+        ("date(\"1969-12-31 23:00\") + 90 min", "1970-01-01 00:30"),
+        ("date(\"2026-10-18  09:30\")", "2026-10-18 09:30"),
+        ("date( \"2026-10-18T09:30:15Z\" ) - n * 1 h", "2026-10-18 05:30:15"),
+        ("now()", "2026-10-19 12:00"),
+        ("now() - date(\"2026-10-18\")", "1.5 days"),
+        ("date(\"2026-10-18 09:00\") - date(\"2026-10-17 21:30\")", "11.5 h"),
+        ("(1 h + 30 min) / 2 - 40 s", "44.333332 min"),
+        ("max(date(\"2026-01-01\"), date(\"2025-12-31\")) + 1 week", "2026-01-08"),
+        ("-abs(2 days - 3 days)", "-1 day"),
+        ("6 h / 2 h + sqrt(16)", "7"),
+    ] {
+        assert_eq!(eval(exp).to_string(), s, "{}", exp);
+    }
+
+    assert_eq!(eval("date(\"2026-10-18 09:00\") - date(\"2026-10-17 21:30\") in hours"), TimeValue::Number(11.5));
+    assert_eq!(eval("(now() - date(\"2026-01-01\")) to days"), TimeValue::Number(291.5));
+    assert_eq!(eval("date(\"2000-03-01\") - date(\"2000-02-01\") in days"), TimeValue::Number(29.0));
+
+    for (exp, err) in [
+        ("date(\"2026-10-18\") * date(\"2026-10-18\")", "\"*\" doesn't work on a date and a date"),
+        ("date(\"2026-10-18\") + date(\"2026-10-18\")", "\"+\" doesn't work on a date and a date"),
+        ("1 + 2 days", "\"+\" doesn't work on a number and a duration"),
+        ("sin(now())", "sin needs a number, not a date"),
+        ("now() in hours", "Only durations can be converted, not a date"),
+        ("3 days in years", "Unknown duration unit: \"years\""),
+        ("date(\"2026-02-29\")", "Invalid date: \"2026-02-29\", expected a date like \"2026-10-18\" or \"2026-10-18 09:30\""),
+        ("date(\"2026-10-18 24:00\")", "Invalid date: \"2026-10-18 24:00\", expected a date like \"2026-10-18\" or \"2026-10-18 09:30\""),
+        ("date(2026)", "Expected a date in quotes, like date(\"2026-10-18\")"),
+        ("now(1)", "Expected \")\" after the argument of now"),
+        ("3 weeks + x", "Unknown variable or unit: \"x\""),
+    ] {
+        assert_eq!(eval_dates_with_clock(exp, &scope, &clock), Err(err.to_string()), "{}", exp);
+    }
+
+    assert!(matches!(eval_dates("now()", &scope), Ok(TimeValue::Date(t)) if t > 1.7e9));
+}
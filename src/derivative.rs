@@ -0,0 +1,209 @@
+use crate::{ast::{check_arguments, Expr, ExprKind, UnaryOp}, functions::Function, simplify::simplify, token_parse::{Operator, Span}, validation::{COMPARISONS, STRINGS}};
+
+/// Differentiates the expression with respect to `var` and simplifies the result.
+/// Any other variable is treated as a constant. Strings have no derivative.
+pub fn differentiate(expr: &Expr, var: &str) -> Result<Expr, String> {
+    Ok(simplify(&derivative(expr, var)?))
+}
+/// The derivative of every node is built from the derivatives of its children,
+/// together with whether the node depends on `var`.
+fn derivative(expr: &Expr, var: &str) -> Result<Expr, String> {
+    expr.try_fold(|expr, children: Vec<(Expr, bool)>| {
+        let contains: Vec<bool> = children.iter().map(|(_, contains)| *contains).collect();
+        let derived = derive(expr, children.into_iter().map(|(d, _)| d), &contains, var)?;
+
+        Ok((derived, matches!(&expr.kind, ExprKind::Var(name) if name == var) || contains.contains(&true)))
+    })
+    .map(|(derived, _)| derived)
+}
+/// The derivative of the node from the derivatives of its children and whether they depend on `var`.
+fn derive(expr: &Expr, mut derived: impl Iterator<Item = Expr>, contains: &[bool], var: &str) -> Result<Expr, String> {
+
+    // every new node gets the span of the node it is derived from
+    let b = Builder { span: expr.span.clone() };
+
+    Ok(match &expr.kind {
+        ExprKind::Num(_) => b.num(0.0),
+        ExprKind::Str(_) => return Err(STRINGS.to_string()),
+        ExprKind::Var(name) => b.num(if name == var { 1.0 } else { 0.0 }),
+        ExprKind::Unary(UnaryOp::Neg, _) => b.neg(derived.next().unwrap()),
+        ExprKind::Binary(o, left, right) => {
+            let (u, v) = (|| left.as_ref().clone(), || right.as_ref().clone());
+            let (du, dv) = (derived.next().unwrap(), derived.next().unwrap());
+
+            match o {
+                Operator::Plus | Operator::Minus => b.bin(*o, du, dv),
+                // (uv)' = u'v + uv'
+                Operator::Mul | Operator::MatMul => b.bin(Operator::Plus, b.bin(*o, du, v()), b.bin(*o, u(), dv)),
+                // (u/v)' = (u'v - uv') / v^2
+                Operator::Div => {
+                    let numerator = b.bin(Operator::Minus, b.bin(Operator::Mul, du, v()), b.bin(Operator::Mul, u(), dv));
+                    b.bin(Operator::Div, numerator, b.bin(Operator::Pow, v(), b.num(2.0)))
+                },
+                Operator::Pow => {
+                    if !contains[1] {
+                        // (u^c)' = c * u^(c - 1) * u'
+                        let power = b.bin(Operator::Pow, u(), b.bin(Operator::Minus, v(), b.num(1.0)));
+                        b.bin(Operator::Mul, b.bin(Operator::Mul, v(), power), du)
+                    }
+                    else if !contains[0] {
+                        // (c^v)' = c^v * ln(c) * v'
+                        b.bin(Operator::Mul, b.bin(Operator::Mul, expr.clone(), b.call(Function::Ln, vec![u()])), dv)
+                    }
+                    else {
+                        // (u^v)' = u^v * (v' * ln(u) + v * u' / u)
+                        let ln = b.bin(Operator::Mul, dv, b.call(Function::Ln, vec![u()]));
+                        let quotient = b.bin(Operator::Div, b.bin(Operator::Mul, v(), du), u());
+                        b.bin(Operator::Mul, expr.clone(), b.bin(Operator::Plus, ln, quotient))
+                    }
+                },
+                _ => return Err(COMPARISONS.to_string()),
+            }
+        },
+        ExprKind::Call(f, args) => {
+            check_arguments(f, args)?;
+
+            let u = args[0].clone();
+            let du = derived.next().unwrap();
+
+            // the derivative of the function at u, multiplied by u' for the chain rule
+            let outer = match f {
+                Function::Sqrt => b.bin(Operator::Div, b.num(0.5), expr.clone()),
+                Function::Abs => b.bin(Operator::Div, u.clone(), expr.clone()),
+                Function::Exp => expr.clone(),
+                Function::Ln => b.bin(Operator::Div, b.num(1.0), u),
+                Function::Log => b.bin(Operator::Div, b.num(1.0), b.bin(Operator::Mul, u, b.num(10f32.ln()))),
+                Function::Sin => b.call(Function::Cos, vec![u]),
+                Function::Cos => b.neg(b.call(Function::Sin, vec![u])),
+                Function::Tan => b.bin(Operator::Div, b.num(1.0), b.bin(Operator::Pow, b.call(Function::Cos, vec![u]), b.num(2.0))),
+                // the variables are real, so these only pass on or drop the value
+                Function::Conj | Function::Re => b.num(1.0),
+                Function::Arg | Function::Im => b.num(0.0),
+                // min(u, v) = (u + v - |u - v|) / 2 and max(u, v) = (u + v + |u - v|) / 2,
+                // with |w|' = w / |w| * w'
+                Function::Min | Function::Max => {
+                    let (v, dv) = (args[1].clone(), derived.next().unwrap());
+                    let difference = b.bin(Operator::Minus, u, v);
+                    let sign = b.bin(Operator::Div, difference.clone(), b.call(Function::Abs, vec![difference]));
+                    let distance = b.bin(Operator::Mul, sign, b.bin(Operator::Minus, du.clone(), dv.clone()));
+                    let o = if *f == Function::Min { Operator::Minus } else { Operator::Plus };
+
+                    return Ok(b.bin(Operator::Div, b.bin(o, b.bin(Operator::Plus, du, dv), distance), b.num(2.0)));
+                },
+                Function::Len | Function::Upper | Function::Substr | Function::Str | Function::Num => return Err(STRINGS.to_string()),
+                Function::Dot => {
+                    let (v, dv) = (args[1].clone(), derived.next().unwrap());
+                    return Ok(b.bin(Operator::Plus, b.call(Function::Dot, vec![du, v]), b.call(Function::Dot, vec![u, dv])));
+                },
+                Function::Transpose => return Ok(b.call(Function::Transpose, vec![du])),
+                Function::Det => return det_derivative(&b, u, du),
+            };
+
+            b.bin(Operator::Mul, outer, du)
+        },
+        ExprKind::List(_) => Expr::new(ExprKind::List(derived.collect()), expr.span.clone()),
+    })
+}
+/// The derivative of `det(u)`. The determinant of a number is the number itself. For a matrix written out
+/// as a list of rows it is the sum of the determinants with one row replaced by its derivative at a time.
+fn det_derivative(b: &Builder, u: Expr, du: Expr) -> Result<Expr, String> {
+
+    let has_lists = u.fold(|e, children: Vec<bool>| matches!(e.kind, ExprKind::List(_)) || children.contains(&true));
+
+    let rows = match u.into_kind() {
+        ExprKind::List(rows) if rows.iter().all(|row| matches!(row.kind, ExprKind::List(_))) => rows,
+        _ if has_lists => return Err("det can only be differentiated for a matrix written out as a list of rows".to_string()),
+        _ => return Ok(du),
+    };
+    let ExprKind::List(derived) = du.into_kind() else { unreachable!("lists are derived element by element") };
+
+    let terms = derived.into_iter().enumerate().map(|(i, row)| {
+        let mut m = rows.clone();
+        m[i] = row;
+        b.call(Function::Det, vec![b.list(m)])
+    });
+    Ok(terms.reduce(|sum, term| b.bin(Operator::Plus, sum, term)).unwrap_or_else(|| b.num(0.0)))
+}
+struct Builder {
+    span: Span,
+}
+impl Builder {
+    fn num(&self, n: f32) -> Expr {
+        Expr::new(ExprKind::Num(n), self.span.clone())
+    }
+    fn neg(&self, e: Expr) -> Expr {
+        Expr::new(ExprKind::Unary(UnaryOp::Neg, Box::new(e)), self.span.clone())
+    }
+    fn bin(&self, o: Operator, left: Expr, right: Expr) -> Expr {
+        Expr::new(ExprKind::Binary(o, Box::new(left), Box::new(right)), self.span.clone())
+    }
+    fn call(&self, f: Function, args: Vec<Expr>) -> Expr {
+        Expr::new(ExprKind::Call(f, args), self.span.clone())
+    }
+    fn list(&self, elements: Vec<Expr>) -> Expr {
+        Expr::new(ExprKind::List(elements), self.span.clone())
+    }
+}
+
+#[test]
+fn differentiation() {
+    use crate::{ast::{evaluate_ast, parse_ast}, matrix::{eval_matrix, evaluate_matrix, parse_matrix_ast}, script::Scope, strings::parse_string_ast};
+
+    for (exp, derived) in [
+        ("x^2", "2*x"),
+        ("3x + y", "3"),
+        ("sin(x) x", "cos(x)*x + sin(x)"),
+        ("1 / x", "-1/x^2"),
+        ("-cos(2x)", "sin(2*x)*2"),
+        ("sqrt(x)", "0.5/sqrt(x)"),
+        ("e^x", "e^x*ln(e)"),
+        ("y^2", "0"),
+    ] {
+        assert_eq!(differentiate(&parse_ast(exp).unwrap(), "x").unwrap().to_string(), derived, "{}", exp);
+    }
+
+    // compare with the slope between two close points
+    for exp in ["x^3 - 2x", "ln(x) / x", "x^x", "tan(x/4)^2", "exp(sqrt(x))", "log(x^2)", "abs(x - 3)", "max(x^2, 4)", "min(x, 2)x"] {
+        let expr = parse_ast(exp).unwrap();
+        let derived = differentiate(&expr, "x").unwrap();
+
+        for x in [0.5f32, 1.5, 2.5, 4.0] {
+            let mut scope = Scope::new();
+            let h = 1e-2;
+
+            scope.set("x", x + h);
+            let high = evaluate_ast(&expr, &scope).unwrap();
+            scope.set("x", x - h);
+            let low = evaluate_ast(&expr, &scope).unwrap();
+
+            scope.set("x", x);
+            let slope = evaluate_ast(&derived, &scope).unwrap();
+
+            assert!((slope - (high - low) / (2.0 * h)).abs() < 1e-2 * slope.abs().max(1.0), "{} at {}: {} is {}", exp, x, derived, slope);
+        }
+    }
+
+    for exp in ["len(str(2)) x", "num(\"2\") + x", "x + len(\"ab\")"] {
+        let expr = parse_string_ast(exp).unwrap();
+
+        assert!(simplify(&expr).same_tree(&expr), "{}", exp);
+        assert_eq!(differentiate(&expr, "x").err().unwrap(), STRINGS, "{}", exp);
+    }
+    // the derivatives of matrices are taken element by element
+    let mut scope = Scope::new();
+    scope.set("x", 3.0);
+
+    for (exp, derived) in [
+        ("det([[x, 1], [1, x]])", "2x"),
+        ("transpose([x, 2x])", "[[1], [2]]"),
+        ("det([[x, 2x], [3, x^2]]) + det(x)", "3x^2 - 6 + 1"),
+        ("transpose([[x, 1], [x^2, 2]]) @ [1, 1]", "[[1, 2x], [0, 0]] @ [1, 1]"),
+    ] {
+        let expr = differentiate(&parse_matrix_ast(exp).unwrap(), "x").unwrap();
+        assert_eq!(evaluate_matrix(&expr, &scope), eval_matrix(derived, &scope), "{}: {}", exp, expr);
+    }
+    assert!(differentiate(&parse_matrix_ast("det(transpose([[x, 1], [1, x]]))").unwrap(), "x").is_err());
+
+    let max = Expr::new(ExprKind::Call(Function::Max, vec![Expr::new(ExprKind::Var("x".to_string()), 0..0)]), 0..0);
+    assert_eq!(differentiate(&max, "x").err().unwrap(), "Function \"max\" expects 2 argument/s");
+}
@@ -0,0 +1,220 @@
+use std::fmt::{self, Write};
+
+use crate::{ast::{Expr, ExprKind, UnaryOp}, functions::{function_from_name, function_to_string}, token_parse::{is_comparison, operator_to_string, precedence, quote, Operator}};
+
+#[derive(Clone, Debug, Default)]
+pub struct FormatOptions {
+    /// Leaves out `*` wherever the parser adds it back, `2x(y + 1)` instead of `2*x*(y + 1)`.
+    pub implicit_mul: bool,
+}
+/// Prints the expression with spaces around `+` and `-` and only the brackets needed to keep its structure.
+///
+/// An expression returned by `parse_ast` is parsed back into the same tree, see `Expr::same_tree`.
+/// The zero `parse_ast` adds before a leading minus, a `0` with an empty span, is left out again.
+pub fn format_expr(expr: &Expr, options: &FormatOptions) -> String {
+
+    let mut s = String::new();
+    write_expr(&mut s, expr, options).unwrap();
+    s
+}
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self, &FormatOptions::default())
+    }
+}
+/// A part of what `write_expr` writes.
+enum Part<'a> {
+    Expr(&'a Expr),
+    Text(&'static str),
+}
+fn write_expr(w: &mut impl Write, e: &Expr, options: &FormatOptions) -> fmt::Result {
+
+    // what is left to write, the last part comes first
+    let mut parts = vec![Part::Expr(e)];
+
+    while let Some(part) = parts.pop() {
+        let e = match part {
+            Part::Text(text) => {
+                w.write_str(text)?;
+                continue;
+            },
+            Part::Expr(e) => e,
+        };
+        let mut next = Vec::new();
+
+        match &e.kind {
+            ExprKind::Num(n) => write!(w, "{}", n)?,
+            ExprKind::Var(name) => write!(w, "{}", name)?,
+            ExprKind::Str(text) => w.write_str(&quote(text))?,
+            ExprKind::Unary(UnaryOp::Neg, operand) => {
+                next.push(Part::Text("-"));
+                push_bracketed(&mut next, operand, negation_brackets(operand));
+            },
+            ExprKind::Binary(Operator::Minus, left, right) if is_implicit_zero(left) => {
+                next.push(Part::Text("-"));
+                push_bracketed(&mut next, right, brackets(right, Operator::Minus, false));
+            },
+            ExprKind::Binary(o, left, right) => {
+                let left_brackets = brackets(left, *o, true);
+                let right_brackets = brackets(right, *o, false);
+
+                push_bracketed(&mut next, left, left_brackets);
+
+                match o {
+                    Operator::Plus | Operator::Minus | Operator::MatMul => next.extend([Part::Text(" "), Part::Text(operator_to_string(o)), Part::Text(" ")]),
+                    _ if is_comparison(o) => next.extend([Part::Text(" "), Part::Text(operator_to_string(o)), Part::Text(" ")]),
+                    Operator::Mul if options.implicit_mul => {
+                        let last = if left_brackets { Edge::Close } else { last(left) };
+                        let first = if right_brackets { Edge::Open } else { first(right) };
+
+                        match (last, first) {
+                            // a name followed by a name would be read as one
+                            (Edge::Variable(_), Edge::Function) => next.push(Part::Text(" ")),
+                            (Edge::Close, Edge::Number(_)) => {},
+                            // `2e3` and `0x` are read as numbers
+                            (Edge::Number(n), Edge::Variable(c)) if c == 'e' || c == 'E' || (n == 0.0 && "xXoObB".contains(c)) => next.push(Part::Text("*")),
+                            (Edge::Number(_) | Edge::Close, Edge::Variable(_)) => {},
+                            (Edge::Number(_) | Edge::Variable(_) | Edge::Close, Edge::Function | Edge::Open) => {},
+                            _ => next.push(Part::Text("*")),
+                        }
+                    },
+                    _ => next.push(Part::Text(operator_to_string(o))),
+                }
+
+                push_bracketed(&mut next, right, right_brackets);
+            },
+            ExprKind::Call(function, args) => {
+                next.extend([Part::Text(function_to_string(function)), Part::Text("(")]);
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 { next.push(Part::Text(", ")); }
+                    next.push(Part::Expr(arg));
+                }
+                next.push(Part::Text(")"));
+            },
+            ExprKind::List(elements) => {
+                next.push(Part::Text("["));
+                for (i, e) in elements.iter().enumerate() {
+                    if i > 0 { next.push(Part::Text(", ")); }
+                    next.push(Part::Expr(e));
+                }
+                next.push(Part::Text("]"));
+            },
+        }
+        parts.extend(next.into_iter().rev());
+    }
+    Ok(())
+}
+fn push_bracketed<'a>(parts: &mut Vec<Part<'a>>, e: &'a Expr, brackets: bool) {
+    if brackets {
+        parts.extend([Part::Text("("), Part::Expr(e), Part::Text(")")]);
+    }
+    else {
+        parts.push(Part::Expr(e));
+    }
+}
+/// Whether the operand of `o` has to be bracketed.
+fn brackets(e: &Expr, o: Operator, left: bool) -> bool {
+    match &e.kind {
+        // a leading minus is only read as a negation at the start of a sum or on either side of a comparison
+        _ if is_negative(e) => !(is_comparison(&o) || (left && precedence(&o) <= 0)),
+        // the same operator on the other side would be parsed the other way around, comparisons aren't chained
+        ExprKind::Binary(child, ..) => precedence(child) < precedence(&o) || (precedence(child) == precedence(&o) && (left == (o == Operator::Pow) || is_comparison(&o))),
+        _ => false,
+    }
+}
+/// Whether the operand of a negation has to be bracketed, a minus binds like a subtraction from zero.
+fn negation_brackets(e: &Expr) -> bool {
+    matches!(&e.kind, ExprKind::Binary(o, ..) if precedence(o) <= 0) || is_negative(e)
+}
+/// Whether the expression is printed with a leading minus.
+pub(crate) fn is_negative(e: &Expr) -> bool {
+    match &e.kind {
+        ExprKind::Num(n) => n.is_sign_negative(),
+        ExprKind::Unary(UnaryOp::Neg, _) => true,
+        ExprKind::Binary(Operator::Minus, left, _) => is_implicit_zero(left),
+        _ => false,
+    }
+}
+pub(crate) fn is_implicit_zero(e: &Expr) -> bool {
+    matches!(e.kind, ExprKind::Num(n) if n == 0.0) && e.span.is_empty()
+}
+/// The kind of token at either end of a printed expression.
+#[derive(Clone, Copy, PartialEq)]
+enum Edge {
+    Number(f32),
+    // with the first letter of the name
+    Variable(char),
+    Function,
+    Open,
+    Close,
+    Minus,
+    // a string is always multiplied with `*`
+    Str,
+}
+fn first(mut e: &Expr) -> Edge {
+    loop {
+        return match &e.kind {
+            _ if is_negative(e) => Edge::Minus,
+            ExprKind::Num(n) => Edge::Number(*n),
+            ExprKind::Var(name) => Edge::Variable(name.chars().next().unwrap()),
+            ExprKind::Call(..) => Edge::Function,
+            ExprKind::List(_) => Edge::Open,
+            ExprKind::Str(_) => Edge::Str,
+            ExprKind::Binary(o, left, _) if brackets(left, *o, true) => Edge::Open,
+            ExprKind::Binary(_, left, _) => {
+                e = left;
+                continue;
+            },
+            ExprKind::Unary(..) => Edge::Minus,
+        };
+    }
+}
+fn last(mut e: &Expr) -> Edge {
+    loop {
+        return match &e.kind {
+            ExprKind::Num(n) => Edge::Number(*n),
+            // a variable named like a function, `len(2)` would be read as a call
+            ExprKind::Var(name) if function_from_name(name).is_some() => Edge::Function,
+            ExprKind::Var(name) => Edge::Variable(name.chars().next().unwrap()),
+            ExprKind::Call(..) | ExprKind::List(_) => Edge::Close,
+            ExprKind::Str(_) => Edge::Str,
+            ExprKind::Unary(_, operand) if negation_brackets(operand) => Edge::Close,
+            ExprKind::Binary(o, _, right) if brackets(right, *o, false) => Edge::Close,
+            ExprKind::Unary(_, operand) | ExprKind::Binary(_, _, operand) => {
+                e = operand;
+                continue;
+            },
+        };
+    }
+}
+
+#[test]
+fn formatting() {
+    use crate::ast::parse_ast;
+
+    let implicit = FormatOptions { implicit_mul: true };
+
+    for (exp, explicit, implicit_mul) in [
+        ("1+2 *3", "1 + 2*3", "1 + 2*3"),
+        ("((1 + 2)) * (3)", "(1 + 2)*3", "(1 + 2)3"),
+        ("-x^2 + (-(y))", "-x^2 + (-y)", "-x^2 + (-y)"),
+        ("2x(y+1)", "2*x*(y + 1)", "2x(y + 1)"),
+        ("x sqrt(x) y", "x*sqrt(x)*y", "x sqrt(x)y"),
+        ("(a - b) - (c - d)", "a - b - (c - d)", "a - b - (c - d)"),
+        ("a / (b / c) / d", "a/(b/c)/d", "a/(b/c)/d"),
+        ("(2^3)^2 + 2^(3^2)", "(2^3)^2 + 2^3^2", "(2^3)^2 + 2^3^2"),
+        ("max(-1, 2 (3)) 4", "max(-1, 2*3)*4", "max(-1, 2*3)4"),
+        ("(x + 1) 2 x", "(x + 1)*2*x", "(x + 1)2x"),
+        ("2 (-x)", "2*(-x)", "2(-x)"),
+        ("2 e + 0 x_1 + 1 exp(1)", "2*e + 0*x_1 + 1*exp(1)", "2*e + 0*x_1 + 1exp(1)"),
+        ("len * (x + 1) + 2 re * (x - 1)", "len*(x + 1) + 2*re*(x - 1)", "len*(x + 1) + 2re*(x - 1)"),
+    ] {
+        let expr = parse_ast(exp).unwrap();
+
+        assert_eq!(format_expr(&expr, &FormatOptions::default()), explicit, "{}", exp);
+        assert_eq!(format_expr(&expr, &implicit), implicit_mul, "{}", exp);
+
+        assert!(parse_ast(explicit).unwrap().same_tree(&expr), "{}", exp);
+        assert!(parse_ast(implicit_mul).unwrap().same_tree(&expr), "{}", exp);
+    }
+}
@@ -0,0 +1,124 @@
+/// Built-in functions, called as `name(arg, ...)`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Function {
+    Sqrt,
+    Abs,
+    Exp,
+    Ln,
+    Log,
+    Sin,
+    Cos,
+    Tan,
+    Min,
+    Max,
+    // the angle, conjugate and parts of a complex number, see `complex`
+    Arg,
+    Conj,
+    Re,
+    Im,
+    // products and the determinant of vectors and matrices, see `matrix`
+    Dot,
+    Transpose,
+    Det,
+    // the length, upper case and part of a string and conversions, see `strings`
+    Len,
+    Upper,
+    Substr,
+    Str,
+    Num,
+}
+pub const MAX_ARGUMENTS: usize = 3;
+
+pub fn function_from_name(name: &str) -> Option<Function> {
+    match name {
+        "sqrt" => Some(Function::Sqrt),
+        "abs" => Some(Function::Abs),
+        "exp" => Some(Function::Exp),
+        "ln" => Some(Function::Ln),
+        "log" => Some(Function::Log),
+        "sin" => Some(Function::Sin),
+        "cos" => Some(Function::Cos),
+        "tan" => Some(Function::Tan),
+        "min" => Some(Function::Min),
+        "max" => Some(Function::Max),
+        "arg" => Some(Function::Arg),
+        "conj" => Some(Function::Conj),
+        "re" => Some(Function::Re),
+        "im" => Some(Function::Im),
+        "dot" => Some(Function::Dot),
+        "transpose" => Some(Function::Transpose),
+        "det" => Some(Function::Det),
+        "len" => Some(Function::Len),
+        "upper" => Some(Function::Upper),
+        "substr" => Some(Function::Substr),
+        "str" => Some(Function::Str),
+        "num" => Some(Function::Num),
+        _ => None,
+    }
+}
+pub fn function_to_string(f: &Function) -> &'static str {
+    match f {
+        Function::Sqrt => "sqrt",
+        Function::Abs => "abs",
+        Function::Exp => "exp",
+        Function::Ln => "ln",
+        Function::Log => "log",
+        Function::Sin => "sin",
+        Function::Cos => "cos",
+        Function::Tan => "tan",
+        Function::Min => "min",
+        Function::Max => "max",
+        Function::Arg => "arg",
+        Function::Conj => "conj",
+        Function::Re => "re",
+        Function::Im => "im",
+        Function::Dot => "dot",
+        Function::Transpose => "transpose",
+        Function::Det => "det",
+        Function::Len => "len",
+        Function::Upper => "upper",
+        Function::Substr => "substr",
+        Function::Str => "str",
+        Function::Num => "num",
+    }
+}
+pub fn argument_count(f: &Function) -> usize {
+    match f {
+        Function::Min | Function::Max | Function::Dot => 2,
+        Function::Substr => 3,
+        _ => 1,
+    }
+}
+/// Whether the function takes or returns strings, which only the string evaluator has.
+pub fn is_string_function(f: &Function) -> bool {
+    matches!(f, Function::Len | Function::Upper | Function::Substr | Function::Str | Function::Num)
+}
+/// Whether the function takes vectors or matrices, which only the matrix evaluator has.
+pub fn is_matrix_function(f: &Function) -> bool {
+    matches!(f, Function::Dot | Function::Transpose | Function::Det)
+}
+/// Whether the function is about the parts of a complex number, which only the complex evaluator has.
+pub fn is_complex_function(f: &Function) -> bool {
+    matches!(f, Function::Arg | Function::Conj | Function::Re | Function::Im)
+}
+/// Whether the function only exists in one of the other evaluators, its name is a variable unless it is called.
+pub fn is_extension_function(f: &Function) -> bool {
+    is_string_function(f) || is_matrix_function(f) || is_complex_function(f)
+}
+pub fn call_function(f: &Function, args: &[f32]) -> f32 {
+    match f {
+        Function::Sqrt => args[0].sqrt(),
+        Function::Abs => args[0].abs(),
+        Function::Exp => args[0].exp(),
+        Function::Ln => args[0].ln(),
+        Function::Log => args[0].log10(),
+        Function::Sin => args[0].sin(),
+        Function::Cos => args[0].cos(),
+        Function::Tan => args[0].tan(),
+        Function::Min => args[0].min(args[1]),
+        Function::Max => args[0].max(args[1]),
+        Function::Dot | Function::Transpose | Function::Det => unreachable!("matrix functions are validated"),
+        Function::Arg | Function::Conj | Function::Re | Function::Im => unreachable!("complex functions are validated"),
+        Function::Len | Function::Upper | Function::Substr | Function::Str | Function::Num => unreachable!("string functions are validated"),
+    }
+}
@@ -0,0 +1,403 @@
+use std::{f64::consts::PI, fmt, ops::{Add, Mul, Neg, Sub}};
+
+use crate::{ast::{ast_from_tokens, check_call, Expr, ExprKind, UnaryOp}, functions::Function, script::Scope, token_parse::{is_comparison, parse_exp, Operator, Span, Token, Tokens}, validation::{Validator, COMPARISONS, LISTS, STRINGS}};
+
+/// The real numbers from `lo` to `hi`. Every operation rounds outward, so the result holds
+/// the exact value of the expression for every choice of values in the operands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+    pub lo: f32,
+    pub hi: f32,
+}
+impl Interval {
+    pub const ENTIRE: Interval = Interval { lo: f32::NEG_INFINITY, hi: f32::INFINITY };
+
+    pub fn new(lo: f32, hi: f32) -> Self {
+        Interval { lo, hi }
+    }
+    pub fn point(x: f32) -> Self {
+        Interval { lo: x, hi: x }
+    }
+    pub fn contains(self, x: f32) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+    pub fn width(self) -> f32 {
+        self.hi - self.lo
+    }
+    /// Division by an interval with zero inside is the whole line, or a half of it when zero
+    /// is a bound. Division by `[0, 0]` is an error.
+    pub fn checked_div(self, b: Interval) -> Result<Interval, String> {
+
+        if b.lo == 0.0 && b.hi == 0.0 {
+            return Err("Division by zero".to_string());
+        }
+        if !b.contains(0.0) {
+            let quotients = [quotient(self.lo, b.lo), quotient(self.lo, b.hi), quotient(self.hi, b.lo), quotient(self.hi, b.hi)];
+            return Ok(bounds(quotients));
+        }
+
+        let inf = f32::INFINITY;
+
+        Ok(match () {
+            _ if self.contains(0.0) || (b.lo < 0.0 && b.hi > 0.0) => Interval::ENTIRE,
+            // the divisor is [0, hi] or [lo, 0]
+            _ if b.lo == 0.0 && self.hi < 0.0 => Interval::new(-inf, quotient(self.hi, b.hi).1),
+            _ if b.lo == 0.0 => Interval::new(quotient(self.lo, b.hi).0, inf),
+            _ if self.hi < 0.0 => Interval::new(quotient(self.hi, b.lo).0, inf),
+            _ => Interval::new(-inf, quotient(self.lo, b.lo).1),
+        })
+    }
+    pub fn pow(self, b: Interval) -> Result<Interval, String> {
+
+        let n = b.lo;
+
+        if b.lo == b.hi && n.fract() == 0.0 && n.abs() <= 1024.0 {
+            // even powers only see the absolute value
+            let base = if n as i32 % 2 == 0 { self.abs() } else { self };
+
+            let mut res = Interval::point(1.0);
+            for _ in 0..n.abs() as i32 {
+                res = res * base;
+            }
+            return if n < 0.0 { Interval::point(1.0).checked_div(res) } else { Ok(res) };
+        }
+        if self.lo <= 0.0 {
+            return Err("Powers with an exponent that isn't a whole number need a positive base".to_string());
+        }
+        Ok((b * self.ln()?).exp())
+    }
+    pub fn abs(self) -> Interval {
+        match () {
+            _ if self.lo >= 0.0 => self,
+            _ if self.hi <= 0.0 => -self,
+            _ => Interval::new(0.0, self.hi.max(-self.lo)),
+        }
+    }
+    pub fn sqrt(self) -> Result<Interval, String> {
+        if self.hi < 0.0 {
+            return Err("sqrt needs values that aren't negative".to_string());
+        }
+        Ok(monotonic(self.lo.max(0.0), self.hi, f64::sqrt))
+    }
+    pub fn exp(self) -> Interval {
+        let res = monotonic(self.lo, self.hi, f64::exp);
+        Interval::new(res.lo.max(0.0), res.hi)
+    }
+    pub fn ln(self) -> Result<Interval, String> {
+        if self.hi <= 0.0 {
+            return Err("ln needs positive values".to_string());
+        }
+        Ok(monotonic(self.lo.max(0.0), self.hi, f64::ln))
+    }
+    pub fn sin(self) -> Interval {
+        periodic(self, f64::sin, PI / 2.0)
+    }
+    pub fn cos(self) -> Interval {
+        periodic(self, f64::cos, 0.0)
+    }
+    pub fn tan(self) -> Interval {
+        let (lo, hi) = (self.lo as f64, self.hi as f64);
+
+        // an asymptote inside makes every value possible
+        if hi - lo >= PI || next_point(lo, PI / 2.0, PI) <= hi {
+            return Interval::ENTIRE;
+        }
+        monotonic(self.lo, self.hi, f64::tan)
+    }
+}
+impl Add for Interval {
+    type Output = Interval;
+    fn add(self, b: Interval) -> Interval {
+        Interval::new(sum(self.lo, b.lo).0, sum(self.hi, b.hi).1)
+    }
+}
+impl Sub for Interval {
+    type Output = Interval;
+    fn sub(self, b: Interval) -> Interval {
+        self + -b
+    }
+}
+impl Mul for Interval {
+    type Output = Interval;
+    fn mul(self, b: Interval) -> Interval {
+        let products = [product(self.lo, b.lo), product(self.lo, b.hi), product(self.hi, b.lo), product(self.hi, b.hi)];
+        bounds(products)
+    }
+}
+impl Neg for Interval {
+    type Output = Interval;
+    fn neg(self) -> Interval {
+        Interval::new(-self.hi, -self.lo)
+    }
+}
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+/// The smallest interval that holds every value.
+fn bounds(values: [(f32, f32); 4]) -> Interval {
+    let lo = values.iter().map(|v| v.0).fold(f32::INFINITY, f32::min);
+    let hi = values.iter().map(|v| v.1).fold(f32::NEG_INFINITY, f32::max);
+    Interval::new(lo, hi)
+}
+/// `x` rounded down and up to a float, `err` is the sign of the exact value minus `x`.
+fn round(x: f64, err: f64) -> (f32, f32) {
+    let f = x as f32;
+    let lo = if f as f64 > x || (f as f64 == x && err < 0.0) { f.next_down() } else { f };
+    let hi = if (f as f64) < x || (f as f64 == x && err > 0.0) { f.next_up() } else { f };
+    (lo, hi)
+}
+fn sum(a: f32, b: f32) -> (f32, f32) {
+    let (a, b) = (a as f64, b as f64);
+    let s = a + b;
+
+    // the rounding error of the sum, exactly
+    let b_part = s - a;
+    let err = (a - (s - b_part)) + (b - b_part);
+
+    round(s, err)
+}
+fn product(a: f32, b: f32) -> (f32, f32) {
+    // the product of two floats is exact in double precision, `0 * inf` is the whole line
+    match a as f64 * b as f64 {
+        p if p.is_nan() => (f32::NEG_INFINITY, f32::INFINITY),
+        p => round(p, 0.0),
+    }
+}
+fn quotient(a: f32, b: f32) -> (f32, f32) {
+    let (a, b) = (a as f64, b as f64);
+    let q = a / b;
+
+    if !q.is_finite() {
+        return round(q, 0.0);
+    }
+    // q * b - a is exact, its sign tells on which side of the exact quotient q is
+    let residual = q.mul_add(b, -a);
+
+    round(q, -residual * b.signum())
+}
+/// Applies an increasing function, widened by a float on each side for its rounding error.
+fn monotonic(lo: f32, hi: f32, f: fn(f64) -> f64) -> Interval {
+    Interval::new((f(lo as f64) as f32).next_down(), (f(hi as f64) as f32).next_up())
+}
+/// The first of the points `at + k*period` from `x` on.
+fn next_point(x: f64, at: f64, period: f64) -> f64 {
+    at + ((x - at) / period).ceil() * period
+}
+/// Applies sin or cos, which have their maximum at `max_at` and minimum half a period later.
+fn periodic(x: Interval, f: fn(f64) -> f64, max_at: f64) -> Interval {
+
+    let (lo, hi) = (x.lo as f64, x.hi as f64);
+
+    if hi - lo >= 2.0 * PI || !(lo.is_finite() && hi.is_finite()) {
+        return Interval::new(-1.0, 1.0);
+    }
+
+    let (a, b) = (f(lo), f(hi));
+    let max = if next_point(lo, max_at, 2.0 * PI) <= hi { 1.0 } else { a.max(b) };
+    let min = if next_point(lo, max_at + PI, 2.0 * PI) <= hi { -1.0 } else { a.min(b) };
+
+    Interval::new((min as f32).next_down().max(-1.0), (max as f32).next_up().min(1.0))
+}
+/// A number as written, widened unless it is a whole number that a float holds exactly.
+fn literal(n: f32) -> Interval {
+    if n.fract() == 0.0 && n.abs() <= 16_777_216.0 {
+        Interval::point(n)
+    }
+    else {
+        Interval::new(n.next_down(), n.next_up())
+    }
+}
+/// Evaluates the input with intervals, written as `[1.9, 2.1]` or `2 ± 0.1`.
+/// Numbers and the constants `pi` and `e` are widened to hold the value they stand for,
+/// other variables of the scope are exact.
+pub fn eval_interval(input: &str, scope: &Scope) -> Result<Interval, String> {
+
+    let mut literals = Vec::new();
+    let tokens = interval_tokens(input, &mut literals)?;
+
+    evaluate(&ast_from_tokens(tokens)?, scope, &literals)
+}
+/// Tokenizes the input with every interval moved to `literals` and replaced by a variable
+/// named by its index in brackets, a name that can't be written.
+fn interval_tokens(input: &str, literals: &mut Vec<Interval>) -> Result<(Vec<Token>, Vec<Span>), String> {
+
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+
+    let mut start = 0;
+
+    while let Some(offset) = input[start..].find(['[', '±']) {
+        let at = start + offset;
+
+        for t in Tokens::new(&input[start..at]) {
+            let (t, span) = t?;
+            tokens.push(t);
+            spans.push(span.start + start..span.end + start);
+        }
+
+        let (interval, span) = if input[at..].starts_with('[') {
+            let end = at + input[at..].find(']').ok_or("Missing \"]\"")? + 1;
+            let (lo, hi) = input[at + 1..end - 1].split_once(',').ok_or("Expected \",\" between the bounds of an interval")?;
+            let (lo, hi) = (bound(lo)?, bound(hi)?);
+
+            if lo.lo > hi.hi {
+                return Err(format!("The lower bound is above the upper bound: \"{}\"", &input[at..end]));
+            }
+            (Interval::new(lo.lo, hi.hi), at..end)
+        }
+        else {
+            let (center, center_span) = match (tokens.pop(), spans.pop()) {
+                (Some(Token::Number(n)), Some(span)) => (literal(n), span),
+                _ => return Err("Expected a number before \"±\"".to_string()),
+            };
+            let after = at + '±'.len_utf8();
+
+            let (radius, end) = match Tokens::new(&input[after..]).next() {
+                Some(Ok((Token::Number(n), span))) => (literal(n), after + span.end),
+                _ => return Err("Expected a number after \"±\"".to_string()),
+            };
+            (Interval::new(sum(center.lo, -radius.hi).0, sum(center.hi, radius.hi).1), center_span.start..end)
+        };
+
+        tokens.push(Token::Variable(format!("[{}]", literals.len())));
+        spans.push(span.clone());
+        literals.push(interval);
+
+        start = span.end;
+    }
+
+    for t in Tokens::new(&input[start..]) {
+        let (t, span) = t?;
+        tokens.push(t);
+        spans.push(span.start + start..span.end + start);
+    }
+
+    Ok((tokens, spans))
+}
+/// A bound of an interval literal, a number with an optional minus.
+fn bound(s: &str) -> Result<Interval, String> {
+    match parse_exp(s)?[..] {
+        [Token::Number(n)] => Ok(literal(n)),
+        [Token::Operator(Operator::Minus), Token::Number(n)] => Ok(-literal(n)),
+        _ => Err(format!("Invalid bound of an interval: \"{}\"", s.trim())),
+    }
+}
+fn evaluate(expr: &Expr, scope: &Scope, literals: &[Interval]) -> Result<Interval, String> {
+    expr.try_fold(|e, values: Vec<Interval>| match &e.kind {
+        ExprKind::Num(n) => Ok(literal(*n)),
+        ExprKind::Var(name) if name.starts_with('[') => Ok(literals[name[1..name.len() - 1].parse::<usize>().unwrap()]),
+        ExprKind::Var(name) => {
+            let n = scope.get(name).ok_or_else(|| format!("Unknown variable: \"{}\"", name))?;
+
+            // the constants are rounded
+            Ok(if name == "pi" || name == "e" { Interval::new(n.next_down(), n.next_up()) } else { Interval::point(n) })
+        },
+        ExprKind::Unary(UnaryOp::Neg, _) => Ok(-values[0]),
+        ExprKind::Binary(Operator::MatMul, ..) => Err(LISTS.to_string()),
+        ExprKind::Binary(o, ..) if is_comparison(o) => Err(COMPARISONS.to_string()),
+        ExprKind::Binary(o, ..) => {
+            let (a, b) = (values[0], values[1]);
+
+            match o {
+                Operator::Plus => Ok(a + b),
+                Operator::Minus => Ok(a - b),
+                Operator::Mul => Ok(a * b),
+                Operator::Div => a.checked_div(b),
+                Operator::Pow => a.pow(b),
+                Operator::MatMul => unreachable!("@ is checked"),
+                _ => unreachable!("comparisons are checked"),
+            }
+        },
+        ExprKind::Call(f, args) => {
+            check_call(f, args, &Validator::new())?;
+
+            let x = values[0];
+
+            Ok(match f {
+                Function::Sqrt => x.sqrt()?,
+                Function::Abs => x.abs(),
+                Function::Exp => x.exp(),
+                Function::Ln => x.ln()?,
+                Function::Log => x.ln()?.checked_div(Interval::point(10.0).ln()?)?,
+                Function::Sin => x.sin(),
+                Function::Cos => x.cos(),
+                Function::Tan => x.tan(),
+                Function::Min | Function::Max => {
+                    let y = values[1];
+
+                    if *f == Function::Min {
+                        Interval::new(x.lo.min(y.lo), x.hi.min(y.hi))
+                    }
+                    else {
+                        Interval::new(x.lo.max(y.lo), x.hi.max(y.hi))
+                    }
+                },
+                Function::Dot | Function::Transpose | Function::Det => unreachable!("matrix functions are checked"),
+                Function::Arg | Function::Conj | Function::Re | Function::Im => unreachable!("complex functions are checked"),
+                Function::Len | Function::Upper | Function::Substr | Function::Str | Function::Num => unreachable!("string functions are checked"),
+            })
+        },
+        ExprKind::List(_) => Err(LISTS.to_string()),
+        ExprKind::Str(_) => Err(STRINGS.to_string()),
+    })
+}
+
+#[test]
+fn interval_arithmetic() {
+    use crate::eval_exp;
+
+    let eval = |exp: &str| eval_interval(exp, &Scope::new()).unwrap_or_else(|err| panic!("{}: {}", exp, err));
+    let inf = f32::INFINITY;
+
+    for exp in ["2 ± 0.1", "[1.9, 2.1]"] {
+        let i = eval(exp);
+        assert!(i.lo as f64 <= 1.9 && 2.1 <= i.hi as f64 && i.width() < 0.2000003, "{}: {}", exp, i);
+    }
+    assert!(eval("[1.9, 2.1] * 2").contains(3.8) && eval("[1.9, 2.1] * 2").width() < 0.41);
+
+    // exact operations aren't widened
+    for (exp, lo, hi) in [
+        ("[2, 2] + 3", 5.0, 5.0),
+        ("2 * 3 - 1", 5.0, 5.0),
+        ("[2, 3] - [2, 3]", -1.0, 1.0),
+        ("[-1, 2]^2", 0.0, 4.0),
+        ("[1, 2]^(-1)", 0.5, 1.0),
+        ("1 / [-1, 1]", -inf, inf),
+        ("1 / [0, 2]", 0.5, inf),
+        ("-1 / [0, 2]", -inf, -0.5),
+        ("[1, 2] / [-4, 0]", -inf, -0.25),
+        ("abs([-3, 1]) + min([1, 5], [2, 3])", 1.0, 6.0),
+        ("sin([0, 4])", -0.7568025, 1.0),
+    ] {
+        let i = eval(exp);
+        assert!(i.lo <= lo && i.hi >= hi && i.lo >= lo.next_down().next_down() && i.hi <= hi.next_up().next_up(), "{}: {}", exp, i);
+    }
+
+    // the exact value is inside
+    let sum = eval("0.1 + 0.2");
+    assert!(sum.lo as f64 <= 0.3 && 0.3 <= sum.hi as f64, "{}", sum);
+    assert!(eval("sin(pi)").contains(0.0) && eval("cos(pi/2)").contains(0.0));
+    assert!(eval("tan([1, 2])") == Interval::ENTIRE);
+
+    for exp in ["1 / 3 * 3", "sqrt(2) * sqrt(2) - 2", "e^(ln(7.5)) - 7.5", "(1.1 - 1)^3 * 1000", "2^0.5 + log(1000) / 3", "tan(1) cos(1) - sin(1)"] {
+        let (i, x) = (eval(exp), eval_exp(exp).unwrap());
+        assert!(i.contains(x) && i.width() < 1e-4, "{}: {} is not around {}", exp, i, x);
+    }
+
+    for (exp, err) in [
+        ("1 / [0, 0]", "Division by zero"),
+        ("[2, 1]", "The lower bound is above the upper bound: \"[2, 1]\""),
+        ("[1 2]", "Expected \",\" between the bounds of an interval"),
+        ("[1, x]", "Invalid bound of an interval: \"x\""),
+        ("(1, 2]", "Unexpected comma outside of function call"),
+        ("[1, 2", "Missing \"]\""),
+        ("x ± 1", "Expected a number before \"±\""),
+        ("[-1, 1]^0.5", "Powers with an exponent that isn't a whole number need a positive base"),
+    ] {
+        assert_eq!(eval_interval(exp, &Scope::new()), Err(err.to_string()), "{}", exp);
+    }
+
+    assert_eq!(Interval::new(1.0, 2.5).to_string(), "[1, 2.5]");
+}
@@ -0,0 +1,227 @@
+use std::fmt::Write;
+
+use crate::{ast::{Expr, ExprKind, UnaryOp}, format::{is_implicit_zero, is_negative, FormatOptions}, functions::{function_to_string, Function}, token_parse::{is_comparison, precedence, Operator}};
+
+/// Prints the expression as LaTeX math. Divisions become fractions and powers superscripts,
+/// so they need no brackets. With `implicit_mul` set, products are written next to each other
+/// unless the right factor starts with a number, otherwise they are joined with `\cdot`.
+pub fn format_latex(expr: &Expr, options: &FormatOptions) -> String {
+
+    let mut s = String::new();
+    write_latex(&mut s, expr, options);
+    s
+}
+/// A part of what `write_latex` writes.
+enum Part<'a> {
+    Expr(&'a Expr),
+    Text(&'static str),
+}
+fn write_latex(s: &mut String, e: &Expr, options: &FormatOptions) {
+
+    // what is left to write, the last part comes first
+    let mut parts = vec![Part::Expr(e)];
+
+    while let Some(part) = parts.pop() {
+        let e = match part {
+            Part::Text(text) => {
+                s.push_str(text);
+                continue;
+            },
+            Part::Expr(e) => e,
+        };
+        let mut next = Vec::new();
+
+        match &e.kind {
+            ExprKind::Num(n) => s.push_str(&n.to_string()),
+            ExprKind::Var(name) => write_name(s, name),
+            ExprKind::Str(text) => {
+                s.push_str("\\text{``");
+                write_text(s, text);
+                s.push_str("''}");
+            },
+            ExprKind::Unary(UnaryOp::Neg, operand) => {
+                next.push(Part::Text("-"));
+                push_grouped(&mut next, operand, is_sum(operand) || is_negative(operand));
+            },
+            ExprKind::Binary(Operator::Minus, left, right) if is_implicit_zero(left) => {
+                next.push(Part::Text("-"));
+                push_grouped(&mut next, right, is_sum(right) || is_negative(right));
+            },
+            ExprKind::Binary(Operator::Div, left, right) => {
+                next.extend([Part::Text("\\frac{"), Part::Expr(left), Part::Text("}{"), Part::Expr(right), Part::Text("}")]);
+            },
+            ExprKind::Binary(Operator::Pow, left, right) => {
+                push_grouped(&mut next, left, base_brackets(left));
+                next.extend([Part::Text("^{"), Part::Expr(right), Part::Text("}")]);
+            },
+            ExprKind::Binary(o, left, right) => {
+                let (left_brackets, right_brackets) = operand_brackets(*o, left, right);
+
+                push_grouped(&mut next, left, left_brackets);
+
+                next.push(Part::Text(match o {
+                    Operator::Plus => " + ",
+                    Operator::Minus => " - ",
+                    Operator::Equal => " = ",
+                    Operator::NotEqual => " \\neq ",
+                    Operator::Less => " < ",
+                    Operator::LessEqual => " \\leq ",
+                    Operator::Greater => " > ",
+                    Operator::GreaterEqual => " \\geq ",
+                    _ if options.implicit_mul && (right_brackets || !starts_with_digit(right)) => " ",
+                    _ => " \\cdot ",
+                }));
+
+                push_grouped(&mut next, right, right_brackets);
+            },
+            ExprKind::Call(f, args) => {
+                let (open, close) = match f {
+                    Function::Sqrt => ("\\sqrt{", "}"),
+                    Function::Abs => ("\\left|", "\\right|"),
+                    Function::Conj => ("\\overline{", "}"),
+                    Function::Exp => ("e^{", "}"),
+                    _ => {
+                        match f {
+                            Function::Ln => s.push_str("\\ln"),
+                            Function::Log => s.push_str("\\log_{10}"),
+                            Function::Sin => s.push_str("\\sin"),
+                            Function::Cos => s.push_str("\\cos"),
+                            Function::Tan => s.push_str("\\tan"),
+                            Function::Min => s.push_str("\\min"),
+                            Function::Max => s.push_str("\\max"),
+                            Function::Arg => s.push_str("\\arg"),
+                            Function::Re => s.push_str("\\operatorname{Re}"),
+                            Function::Im => s.push_str("\\operatorname{Im}"),
+                            Function::Det => s.push_str("\\det"),
+                            Function::Dot => s.push_str("\\operatorname{dot}"),
+                            Function::Transpose => s.push_str("\\operatorname{transpose}"),
+                            f => write!(s, "\\operatorname{{{}}}", function_to_string(f)).unwrap(),
+                        }
+                        ("\\left(", "\\right)")
+                    },
+                };
+
+                next.push(Part::Text(open));
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 { next.push(Part::Text(", ")); }
+                    next.push(Part::Expr(arg));
+                }
+                next.push(Part::Text(close));
+            },
+            ExprKind::List(elements) => {
+                // a list of lists is a matrix with a row for every list, other lists are a row
+                let rows = if elements.iter().all(|e| matches!(e.kind, ExprKind::List(_))) {
+                    elements.iter().map(|e| match &e.kind { ExprKind::List(row) => row.as_slice(), _ => unreachable!() }).collect()
+                }
+                else {
+                    vec![elements.as_slice()]
+                };
+
+                next.push(Part::Text("\\begin{bmatrix}"));
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 { next.push(Part::Text(" \\\\ ")); }
+                    for (j, e) in row.iter().enumerate() {
+                        if j > 0 { next.push(Part::Text(" & ")); }
+                        next.push(Part::Expr(e));
+                    }
+                }
+                next.push(Part::Text("\\end{bmatrix}"));
+            },
+        }
+        parts.extend(next.into_iter().rev());
+    }
+}
+fn push_grouped<'a>(parts: &mut Vec<Part<'a>>, e: &'a Expr, brackets: bool) {
+    if brackets {
+        parts.extend([Part::Text("\\left("), Part::Expr(e), Part::Text("\\right)")]);
+    }
+    else {
+        parts.push(Part::Expr(e));
+    }
+}
+/// Whether the base of a power is bracketed, a fraction, power or function call as base is bracketed to show what is raised.
+fn base_brackets(e: &Expr) -> bool {
+    !matches!(e.kind, ExprKind::Num(_) | ExprKind::Var(_)) || is_negative(e)
+}
+/// Whether the operands of an operator other than a division or power are bracketed.
+fn operand_brackets(o: Operator, left: &Expr, right: &Expr) -> (bool, bool) {
+    match o {
+        Operator::Mul | Operator::MatMul => (is_sum(left) || is_negative(left), is_sum(right) || is_negative(right)),
+        _ if is_comparison(&o) => (is_compared(left), is_compared(right)),
+        // only the right side of a subtraction changes with its grouping
+        _ => (is_compared(left), (o == Operator::Minus && is_sum(right)) || is_compared(right) || is_negative(right)),
+    }
+}
+/// Whether the LaTeX of the expression starts with a number, which can't follow another one without `\cdot`.
+fn starts_with_digit(mut e: &Expr) -> bool {
+    loop {
+        e = match &e.kind {
+            ExprKind::Num(n) => return n.to_string().starts_with(|c: char| c.is_ascii_digit() || c == '.'),
+            ExprKind::Binary(Operator::Pow, left, _) if !base_brackets(left) => left,
+            ExprKind::Binary(o, left, right) if !matches!(o, Operator::Div | Operator::Pow) && !is_implicit_zero(left) && !operand_brackets(*o, left, right).0 => left,
+            _ => return false,
+        };
+    }
+}
+/// Single letters are printed as they are, `pi` as its letter and longer names upright.
+fn write_name(s: &mut String, name: &str) {
+    if name == "pi" {
+        s.push_str("\\pi");
+    }
+    else if name.len() == 1 {
+        s.push_str(name);
+    }
+    else {
+        s.push_str("\\mathrm{");
+        s.push_str(&name.replace('_', "\\_"));
+        s.push('}');
+    }
+}
+/// Writes a string for `\text{}`, with the characters LaTeX reads as commands escaped.
+fn write_text(s: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '\\' => s.push_str("\\textbackslash{}"),
+            '~' => s.push_str("\\textasciitilde{}"),
+            '^' => s.push_str("\\textasciicircum{}"),
+            '{' | '}' | '%' | '&' | '_' | '#' | '$' => {
+                s.push('\\');
+                s.push(c);
+            },
+            c => s.push(c),
+        }
+    }
+}
+/// Whether the expression is a sum, difference or comparison, which bind looser than a product.
+fn is_sum(e: &Expr) -> bool {
+    matches!(&e.kind, ExprKind::Binary(o, ..) if precedence(o) <= 0)
+}
+/// Whether the expression is a comparison, which binds looser than everything else.
+fn is_compared(e: &Expr) -> bool {
+    matches!(&e.kind, ExprKind::Binary(o, ..) if is_comparison(o))
+}
+
+#[test]
+fn latex_output() {
+    use crate::ast::parse_ast;
+
+    let implicit = FormatOptions { implicit_mul: true };
+
+    for (exp, latex) in [
+        ("1 + 2 * 3", "1 + 2 \\cdot 3"),
+        ("(a + b) / (c - 1)", "\\frac{a + b}{c - 1}"),
+        ("x^(2y) - (x^2)^3", "x^{2 \\cdot y} - \\left(x^{2}\\right)^{3}"),
+        ("-sqrt(x + 1) * (1 - x)", "-\\sqrt{x + 1} \\cdot \\left(1 - x\\right)"),
+        ("a - (b - c) + (d + e)", "a - \\left(b - c\\right) + d + e"),
+        ("2pi*r^2 + (-x)", "2 \\cdot \\pi \\cdot r^{2} + \\left(-x\\right)"),
+        ("abs(x) + exp(-x) + ln(rate_1) + max(1, 2)", "\\left|x\\right| + e^{-x} + \\ln\\left(\\mathrm{rate\\_1}\\right) + \\max\\left(1, 2\\right)"),
+        ("(1/2)^x", "\\left(\\frac{1}{2}\\right)^{x}"),
+    ] {
+        assert_eq!(format_latex(&parse_ast(exp).unwrap(), &FormatOptions::default()), latex, "{}", exp);
+    }
+
+    assert_eq!(format_latex(&parse_ast("2pi*r^2 * 3 sin(x)").unwrap(), &implicit), "2 \\pi r^{2} \\cdot 3 \\sin\\left(x\\right)");
+
+    let text = crate::strings::parse_string_ast("\"50% & {a_b} #1 \\\\ $2^~\" + x").unwrap();
+    assert_eq!(format_latex(&text, &FormatOptions::default()), "\\text{``50\\% \\& \\{a\\_b\\} \\#1 \\textbackslash{} \\$2\\textasciicircum{}\\textasciitilde{}''} + x");
+}
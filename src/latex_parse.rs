@@ -0,0 +1,402 @@
+use crate::{functions::{function_from_name, Function}, token_parse::{Operator, Span, Token}};
+
+/// Parses LaTeX math into the tokens `parse_exp` returns for the same expression.
+pub fn parse_latex(input: &str) -> Result<Vec<Token>, String> {
+    parse_latex_spans(input).map(|(tokens, _)| tokens)
+}
+/// Like `parse_latex`, also returns the span of every token. Tokens that stand for a command
+/// get the span of the command, added multiplications an empty span.
+///
+/// Single letters are separate variables and `x_1` or `x_{max}` are names. Functions take a
+/// bracketed argument, or everything up to the next `+`, `-`, product or bracket as in `\sin 2x`.
+pub fn parse_latex_spans(input: &str) -> Result<(Vec<Token>, Vec<Span>), String> {
+
+    let mut parser = LatexParser { input, index: 0, tokens: Vec::new(), spans: Vec::new(), groups: Vec::new(), script: false };
+
+    while parser.index < input.len() {
+        parser.next()?;
+    }
+
+    parser.close_arguments();
+
+    if let Some(group) = parser.groups.last() {
+        return Err(format!("Missing \"{}\"", group.closing()));
+    }
+
+    Ok((parser.tokens, parser.spans))
+}
+/// An open group, every group adds brackets around its tokens.
+enum Group {
+    Brace,
+    // a bracket closed by the character, written with `\left` or not
+    Bracket(char, bool),
+    // absolute value between `|`, written with `\left` or not
+    Bar(bool),
+    Numerator,
+    Denominator,
+    // the index of `\sqrt[n]`, starting at the token
+    RootIndex(usize),
+    // the radicand of `\sqrt[n]{x}`, with the tokens and spans of the index
+    Root(Vec<Token>, Vec<Span>),
+    // a function argument without brackets
+    Argument,
+}
+impl Group {
+    fn closing(&self) -> &'static str {
+        match self {
+            Group::Brace | Group::Numerator | Group::Denominator | Group::Root(..) => "}",
+            Group::Bracket(')', false) => ")",
+            Group::Bracket(_, false) => "]",
+            Group::Bracket(..) | Group::Bar(true) => "\\right",
+            Group::Bar(false) => "|",
+            Group::RootIndex(_) => "]",
+            Group::Argument => ")",
+        }
+    }
+}
+struct LatexParser<'a> {
+    input: &'a str,
+    index: usize,
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+    groups: Vec<Group>,
+    // the next number or name is a superscript without braces and only one character long
+    script: bool,
+}
+impl LatexParser<'_> {
+    fn next(&mut self) -> Result<(), String> {
+
+        let start = self.index;
+        let c = self.input[start..].chars().next().unwrap();
+        self.index += c.len_utf8();
+
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {},
+            '0'..='9' | '.' => {
+                self.index = start;
+                let len = if self.script { 1 } else { self.input[start..].find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(self.input.len() - start) };
+                self.index = start + len;
+
+                let s = &self.input[start..self.index];
+                let n = s.parse().map_err(|_| format!("Invalid number format: \"{}\"", s))?;
+
+                self.operand(Token::Number(n), start);
+                self.script = false;
+            },
+            c if c.is_ascii_alphabetic() => {
+                let mut name = c.to_string();
+
+                if self.peek() == Some('_') {
+                    self.index += 1;
+                    name.push('_');
+                    name.push_str(&self.subscript()?);
+                }
+                self.operand(Token::Variable(name), start);
+                self.script = false;
+            },
+            '+' | '-' => {
+                // a sign at the start of an argument belongs to it
+                if !matches!(self.tokens.last(), Some(Token::Bracket(true))) {
+                    self.close_arguments();
+                }
+                let o = if c == '+' { Operator::Plus } else { Operator::Minus };
+                self.push(Token::Operator(o), start..self.index);
+            },
+            '*' => self.operator(Operator::Mul, start),
+            '/' => self.operator(Operator::Div, start),
+            '^' => {
+                self.push(Token::Operator(Operator::Pow), start..self.index);
+                self.script = true;
+            },
+            ',' => {
+                self.close_arguments();
+                self.push(Token::Comma, start..self.index);
+            },
+            '{' => self.open(Group::Brace, start),
+            '(' => self.open(Group::Bracket(')', false), start),
+            '[' => self.open(Group::Bracket(']', false), start),
+            '}' | ')' | ']' => self.close(c, false, start)?,
+            '|' => {
+                if matches!(self.groups.last(), Some(Group::Bar(false))) && self.ends_operand() {
+                    self.close('|', false, start)?;
+                }
+                else {
+                    self.operand(Token::Function(Function::Abs), start);
+                    self.open(Group::Bar(false), start);
+                }
+            },
+            '\\' => self.command(start)?,
+            c => return Err(format!("Unexpected character: \"{}\"", c)),
+        }
+        Ok(())
+    }
+    fn command(&mut self, start: usize) -> Result<(), String> {
+
+        let len = self.input[self.index..].find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(self.input.len() - self.index);
+
+        // commands that aren't words are a single character
+        let len = if len == 0 { self.peek().map_or(0, |c| c.len_utf8()) } else { len };
+        let name = &self.input[self.index..self.index + len];
+        self.index += len;
+
+        let span = start..self.index;
+
+        match name {
+            "," | ";" | ":" | "!" | " " | "quad" | "qquad" => {},
+            "cdot" | "times" => self.operator(Operator::Mul, start),
+            "div" => self.operator(Operator::Div, start),
+            "frac" | "dfrac" | "tfrac" => {
+                self.operand(Token::Bracket(true), start);
+                self.expect('{')?;
+                self.open(Group::Numerator, start);
+            },
+            "sqrt" => {
+                if self.skip_spaces() == Some('[') {
+                    self.index += 1;
+                    self.operand(Token::Bracket(true), start);
+                    self.groups.push(Group::RootIndex(self.tokens.len()));
+                }
+                else {
+                    self.function(Function::Sqrt, start);
+                }
+            },
+            "left" => {
+                let c = self.skip_spaces().ok_or("Expected a bracket after \\left")?;
+                self.index += c.len_utf8();
+
+                match c {
+                    '(' => self.open(Group::Bracket(')', true), start),
+                    '[' => self.open(Group::Bracket(']', true), start),
+                    '|' => {
+                        self.operand(Token::Function(Function::Abs), start);
+                        self.open(Group::Bar(true), start);
+                    },
+                    _ => return Err(format!("Unsupported bracket after \\left: \"{}\"", c)),
+                }
+            },
+            "right" => {
+                let c = self.skip_spaces().ok_or("Expected a bracket after \\right")?;
+                self.index += c.len_utf8();
+                self.close(c, true, start)?;
+            },
+            "log" => {
+                // only the default base can be written out
+                if self.skip_spaces() == Some('_') {
+                    self.index += 1;
+                    if self.subscript()? != "10" {
+                        return Err("Only logarithms to base 10 are supported, use \\ln(x) / \\ln(b)".to_string());
+                    }
+                }
+                self.function(Function::Log, start);
+            },
+            "sin" | "cos" | "tan" | "ln" | "exp" | "min" | "max" | "arg" | "det" => {
+                self.function(function_from_name(name).unwrap(), start);
+            },
+            "Re" | "Im" => self.function(function_from_name(&name.to_lowercase()).unwrap(), start),
+            "overline" => self.function(Function::Conj, start),
+            "operatorname" => {
+                // `\operatorname{Re}` is written with a capital
+                let name = self.braced_name()?;
+                let f = function_from_name(&name.to_lowercase()).ok_or_else(|| format!("Unknown function: \"{}\"", name))?;
+                self.function(f, start);
+            },
+            "mathrm" | "mathit" | "text" => {
+                let name = self.braced_name()?;
+                self.operand(Token::Variable(name), start);
+                self.script = false;
+            },
+            "pi" | "alpha" | "beta" | "gamma" | "delta" | "epsilon" | "theta" | "lambda" | "mu" | "rho" | "sigma" | "tau" | "phi" | "omega" => {
+                self.operand(Token::Variable(name.to_string()), start);
+                self.script = false;
+            },
+            _ => return Err(format!("Unknown LaTeX command: \"\\{}\"", name)),
+        }
+
+        if let Some(s) = self.spans.last_mut() {
+            if s.start == start { s.end = s.end.max(span.end); }
+        }
+        Ok(())
+    }
+    /// Adds the function, the argument is bracketed if the next token doesn't open a bracket.
+    fn function(&mut self, f: Function, start: usize) {
+
+        self.operand(Token::Function(f), start);
+        self.script = false;
+
+        let bracket = matches!(self.skip_spaces(), Some('(' | '{' | '[')) || ["\\left(", "\\left["].iter().any(|b| self.input[self.index..].starts_with(b));
+
+        if self.input[self.index..].starts_with('^') {
+            // handled by the validation, a power of a function needs brackets
+            return;
+        }
+        if !bracket {
+            self.push(Token::Bracket(true), self.index..self.index);
+            self.groups.push(Group::Argument);
+        }
+    }
+    fn operator(&mut self, o: Operator, start: usize) {
+        self.close_arguments();
+        self.push(Token::Operator(o), start..self.index);
+    }
+    fn open(&mut self, group: Group, start: usize) {
+        self.operand(Token::Bracket(true), start);
+        self.groups.push(group);
+        self.script = false;
+    }
+    /// Closes the innermost group with the character, `\right` is set for `\right`.
+    fn close(&mut self, c: char, right: bool, start: usize) -> Result<(), String> {
+
+        self.close_arguments();
+
+        let span = start..self.index;
+
+        let group = self.groups.pop().ok_or_else(|| format!("Unexpected \"{}\"", c))?;
+
+        match (group, c) {
+            (Group::Brace, '}') => self.push(Token::Bracket(false), span),
+            (Group::Bracket(closing, left), c) if closing == c && left == right => self.push(Token::Bracket(false), span),
+            (Group::Bar(left), '|') if left == right => self.push(Token::Bracket(false), span),
+            (Group::Numerator, '}') => {
+                self.push(Token::Bracket(false), span.clone());
+                self.push(Token::Operator(Operator::Div), span);
+                self.expect('{')?;
+                let start = self.index - 1;
+                self.open(Group::Denominator, start);
+            },
+            (Group::Denominator, '}') => {
+                self.push(Token::Bracket(false), span.clone());
+                self.push(Token::Bracket(false), span);
+            },
+            (Group::RootIndex(first), ']') => {
+                let index = self.tokens.split_off(first);
+                let index_spans = self.spans.split_off(first);
+
+                self.expect('{')?;
+                self.push(Token::Bracket(true), span.clone());
+                self.groups.push(Group::Root(index, index_spans));
+            },
+            (Group::Root(index, index_spans), '}') => {
+                // the root is the power to the inverse of the index
+                for t in [Token::Bracket(false), Token::Operator(Operator::Pow), Token::Bracket(true), Token::Number(1.0), Token::Operator(Operator::Div), Token::Bracket(true)] {
+                    self.push(t, span.clone());
+                }
+                self.tokens.extend(index);
+                self.spans.extend(index_spans);
+
+                for _ in 0..3 {
+                    self.push(Token::Bracket(false), span.clone());
+                }
+            },
+            (group, _) => return Err(format!("Expected \"{}\" before \"{}\"", group.closing(), &self.input[start..self.index])),
+        }
+        Ok(())
+    }
+    /// Closes the arguments of functions written without brackets.
+    fn close_arguments(&mut self) {
+        while let Some(Group::Argument) = self.groups.last() {
+            self.groups.pop();
+            self.push(Token::Bracket(false), self.index..self.index);
+        }
+    }
+    /// Adds a token that starts an operand, with a multiplication if it follows another one.
+    fn operand(&mut self, t: Token, start: usize) {
+        if self.ends_operand() {
+            self.push(Token::Operator(Operator::Mul), start..start);
+        }
+        self.push(t, start..self.index);
+    }
+    fn ends_operand(&self) -> bool {
+        matches!(self.tokens.last(), Some(Token::Number(_) | Token::Variable(_) | Token::Bracket(false)))
+    }
+    fn push(&mut self, t: Token, span: Span) {
+        self.tokens.push(t);
+        self.spans.push(span);
+    }
+    fn peek(&self) -> Option<char> {
+        self.input[self.index..].chars().next()
+    }
+    fn skip_spaces(&mut self) -> Option<char> {
+        while let Some(' ') = self.peek() {
+            self.index += 1;
+        }
+        self.peek()
+    }
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.skip_spaces() != Some(c) {
+            return Err(format!("Expected \"{}\" at {}", c, self.index));
+        }
+        self.index += 1;
+        Ok(())
+    }
+    /// Reads a subscript, a single character or the letters and digits in braces.
+    fn subscript(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('{') => self.braced_name(),
+            Some(c) if c.is_ascii_alphanumeric() => {
+                self.index += 1;
+                Ok(c.to_string())
+            },
+            _ => Err(format!("Expected a subscript at {}", self.index)),
+        }
+    }
+    /// Reads a name in braces, `\_` is read as `_`.
+    fn braced_name(&mut self) -> Result<String, String> {
+
+        self.expect('{')?;
+
+        let len = self.input[self.index..].find('}').ok_or("Missing \"}\"")?;
+        let name = self.input[self.index..self.index + len].replace("\\_", "_");
+        self.index += len + 1;
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid name: \"{}\"", name));
+        }
+        Ok(name)
+    }
+}
+
+#[test]
+fn latex_parsing() {
+    use crate::{ast::{parse_ast, parse_latex_ast}, eval_exp, token_parse::token_to_string, format::FormatOptions, latex::format_latex, prepare_latex_tokens, linear_eval::linear_eval, script::{resolve_variables, Scope}};
+
+    for (latex, exp) in [
+        ("\\frac{1}{2} \\cdot (3 + x^{2})", "(1/2) * (3 + x^2)"),
+        ("\\sqrt{x}", "sqrt(x)"),
+        ("2x^2y", "2 x^2 y"),
+        ("\\frac{-b + \\sqrt{b^2 - 4ac}}{2a}", "(-b + sqrt(b^2 - 4a*c)) / (2a)"),
+        ("\\left(x_1 + x_{max}\\right) \\times 3", "(x_1 + x_max) * 3"),
+        ("\\sin 2x + \\cos(x) \\ln\\left|x\\right|", "sin(2x) + cos(x) ln(abs(x))"),
+        ("|a - |b|| \\div 2", "abs(a - abs(b)) / 2"),
+        ("\\sqrt[3]{x + 1}", "(x + 1)^(1/3)"),
+        ("2\\pi r", "2 pi*r"),
+        ("\\max(1, \\operatorname{min}(a, b)) + \\mathrm{rate\\_1}", "max(1, min(a, b)) + rate_1"),
+        ("\\log_{10} x^3 - e^{-x}", "log(x^3) - e^(-x)"),
+    ] {
+        let expected = parse_ast(exp).unwrap();
+        let parsed = parse_latex_ast(latex).unwrap_or_else(|err| panic!("{}: {}", latex, err));
+
+        assert!(parsed.same_tree(&expected), "{}: {} is not {}", latex, parsed, expected);
+
+        // the printed LaTeX is read back into the same tree
+        let printed = format_latex(&expected, &FormatOptions::default());
+        assert!(parse_latex_ast(&printed).unwrap().same_tree(&expected), "{}", printed);
+    }
+
+    let mut tokens = prepare_latex_tokens("\\frac{\\sqrt{16}}{2^3} \\cdot \\pi").unwrap();
+    resolve_variables(&mut tokens, &Scope::new()).unwrap();
+    assert_eq!(linear_eval(tokens), eval_exp("sqrt(16) / 2^3 * pi").unwrap());
+
+    // the functions of complex numbers are read, but only the complex evaluator takes them
+    let strings = |tokens: Vec<Token>| tokens.iter().map(token_to_string).collect::<Vec<_>>();
+    let complex = "\\overline{z} + \\operatorname{Re} z - \\arg(z)";
+    assert_eq!(strings(parse_latex(complex).unwrap()), strings(crate::token_parse::parse_exp("conj(z) + re(z) - arg(z)").unwrap()));
+    assert_eq!(parse_latex_ast(complex).err().unwrap(), crate::validation::COMPLEX);
+
+    let (_, spans) = parse_latex_spans("\\frac{1}{x}").unwrap();
+    assert_eq!(spans[0], 0..5);
+
+    assert_eq!(parse_latex("\\frac{1}{2").err().unwrap(), "Missing \"}\"");
+    assert_eq!(parse_latex("\\left(x]").err().unwrap(), "Expected \"\\right\" before \"]\"");
+    assert_eq!(parse_latex("\\int x").err().unwrap(), "Unknown LaTeX command: \"\\int\"");
+    assert_eq!(parse_latex("\\log_2 x").err().unwrap(), "Only logarithms to base 10 are supported, use \\ln(x) / \\ln(b)");
+}
@@ -0,0 +1,99 @@
+use std::{io::Read, slice};
+
+use bytecode::StreamEvaluator;
+use linear_eval::linear_eval;
+use latex_parse::parse_latex;
+use script::{resolve_variables, Scope};
+use locale::NumberFormat;
+use token_parse::{parse_exp, parse_exp_with_format, ReadTokens, Span, Token};
+use validation::{add_implicit_tokens, validate_tokens_with_depth, Implicit, Validator, MAX_DEPTH};
+
+pub mod token_parse;
+pub mod functions;
+pub mod validation;
+pub mod token_tree;
+pub mod linear_eval;
+pub mod bytecode;
+pub mod parallel;
+pub mod ast;
+pub mod script;
+pub mod compiled;
+pub mod batch;
+pub mod limits;
+pub mod simplify;
+pub mod derivative;
+pub mod format;
+pub mod latex;
+pub mod latex_parse;
+pub mod locale;
+pub mod complex;
+pub mod units;
+pub mod interval;
+pub mod matrix;
+pub mod dates;
+pub mod strings;
+
+pub fn eval_exp(input: &str) -> Result<f32, String> {
+    eval_exp_in(input, &Scope::new())
+}
+pub fn eval_exp_in(input: &str, scope: &Scope) -> Result<f32, String> {
+    eval_exp_with_format(input, scope, &NumberFormat::default())
+}
+/// Like `eval_exp_in`, numbers and argument lists are written as set in `format`.
+pub fn eval_exp_with_format(input: &str, scope: &Scope, format: &NumberFormat) -> Result<f32, String> {
+
+    let mut tokens = prepare(parse_exp_with_format(input, format)?, MAX_DEPTH)?;
+
+    resolve_variables(&mut tokens, scope)?;
+
+    Ok(linear_eval(tokens))
+}
+/// Evaluates everything `reader` returns while it is read, so the input never has to fit in memory.
+pub fn eval_read(reader: impl Read, scope: &Scope) -> Result<f32, String> {
+    eval_tokens(ReadTokens::new(reader), scope)
+}
+/// Validates and evaluates tokens one at a time, as returned by `Tokens` or `ReadTokens`.
+/// Only the open brackets and pending operators are kept.
+pub fn eval_tokens(tokens: impl Iterator<Item = Result<(Token, Span), String>>, scope: &Scope) -> Result<f32, String> {
+
+    let mut validator = Validator::new();
+    let mut implicit = Implicit::new();
+    let mut eval = StreamEvaluator::new();
+
+    for t in tokens {
+        let (mut t, _) = t?;
+
+        validator.push(&t)?;
+
+        if let Some(i) = implicit.before(&t) {
+            eval.push(&i);
+        }
+
+        resolve_variables(slice::from_mut(&mut t), scope)?;
+        eval.push(&t);
+    }
+
+    validator.finish()?;
+
+    Ok(eval.finish())
+}
+/// Parses and validates the input and adds the implicit tokens, variables are left unresolved.
+pub fn prepare_tokens(input: &str) -> Result<Vec<Token>, String> {
+    prepare_tokens_with_depth(input, MAX_DEPTH)
+}
+/// Like `prepare_tokens`, brackets may be nested at most `max_depth` levels deep.
+pub fn prepare_tokens_with_depth(input: &str, max_depth: usize) -> Result<Vec<Token>, String> {
+    prepare(parse_exp(input)?, max_depth)
+}
+/// Like `prepare_tokens` for LaTeX math, see `parse_latex`.
+pub fn prepare_latex_tokens(input: &str) -> Result<Vec<Token>, String> {
+    prepare(parse_latex(input)?, MAX_DEPTH)
+}
+fn prepare(mut tokens: Vec<Token>, max_depth: usize) -> Result<Vec<Token>, String> {
+
+    validate_tokens_with_depth(&tokens, max_depth)?;
+
+    add_implicit_tokens(&mut tokens);
+
+    Ok(tokens)
+}
@@ -0,0 +1,233 @@
+use std::{cell::Cell, fmt, io::Read, slice, time::{Duration, Instant}};
+
+use crate::{bytecode::{Instruction, StreamEvaluator}, script::{resolve_variables, Scope}, token_parse::{Operator, ReadTokens, Span, Token, Tokens}, validation::{Implicit, Validator}};
+
+/// Limits for evaluating expressions from untrusted sources with `eval_limited` and `eval_read_limited`.
+#[derive(Clone, Debug)]
+pub struct EvalLimits {
+    /// Length of the input in bytes.
+    pub max_input_length: usize,
+    pub max_tokens: usize,
+    /// Nesting depth of brackets, function calls included.
+    pub max_depth: usize,
+    pub max_function_calls: usize,
+    /// Largest absolute value allowed on the right of `^`.
+    pub max_exponent: f32,
+    /// Steps of the evaluator, every token and every evaluated instruction counts as one.
+    pub max_steps: usize,
+    pub timeout: Option<Duration>,
+}
+impl Default for EvalLimits {
+    fn default() -> Self {
+        EvalLimits {
+            max_input_length: 100_000,
+            max_tokens: 10_000,
+            max_depth: 100,
+            max_function_calls: 1_000,
+            max_exponent: 1_000.0,
+            max_steps: 100_000,
+            timeout: Some(Duration::from_millis(100)),
+        }
+    }
+}
+/// The limit that was exceeded, with its value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LimitExceeded {
+    InputLength(usize),
+    Tokens(usize),
+    Depth(usize),
+    FunctionCalls(usize),
+    Exponent(f32),
+    Steps(usize),
+    Timeout(Duration),
+}
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::InputLength(n) => write!(f, "Input longer than {} bytes", n),
+            LimitExceeded::Tokens(n) => write!(f, "More than {} tokens", n),
+            LimitExceeded::Depth(n) => write!(f, "Brackets nested deeper than {} levels", n),
+            LimitExceeded::FunctionCalls(n) => write!(f, "More than {} function calls", n),
+            LimitExceeded::Exponent(n) => write!(f, "Exponent larger than {}", n),
+            LimitExceeded::Steps(n) => write!(f, "More than {} evaluation steps", n),
+            LimitExceeded::Timeout(d) => write!(f, "Evaluation took longer than {:?}", d),
+        }
+    }
+}
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalError {
+    /// The expression can't be evaluated, with the same message the other evaluators return.
+    Invalid(String),
+    Limit(LimitExceeded),
+}
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Invalid(err) => write!(f, "{}", err),
+            EvalError::Limit(limit) => write!(f, "Limit exceeded: {}", limit),
+        }
+    }
+}
+impl std::error::Error for EvalError {}
+
+impl From<String> for EvalError {
+    fn from(err: String) -> Self {
+        EvalError::Invalid(err)
+    }
+}
+impl From<LimitExceeded> for EvalError {
+    fn from(limit: LimitExceeded) -> Self {
+        EvalError::Limit(limit)
+    }
+}
+/// Evaluates the input within `limits`. The input is checked while it is evaluated,
+/// so nothing more than the limits allow is parsed, stored or computed.
+pub fn eval_limited(input: &str, scope: &Scope, limits: &EvalLimits) -> Result<f32, EvalError> {
+
+    if input.len() > limits.max_input_length {
+        return Err(LimitExceeded::InputLength(limits.max_input_length).into());
+    }
+
+    eval_tokens_limited(Tokens::new(input), scope, limits)
+}
+/// Like `eval_limited`, reads at most one byte more than `max_input_length` from `reader`.
+pub fn eval_read_limited(reader: impl Read, scope: &Scope, limits: &EvalLimits) -> Result<f32, EvalError> {
+
+    let read = Cell::new(0);
+    let res = eval_tokens_limited(ReadTokens::new(Counted { reader: reader.take(limits.max_input_length as u64 + 1), read: &read }), scope, limits);
+
+    // a token cut off by the limit can fail to parse, so the length is checked first
+    if read.get() > limits.max_input_length {
+        return Err(LimitExceeded::InputLength(limits.max_input_length).into());
+    }
+    res
+}
+/// Counts the bytes read.
+struct Counted<'a, R> {
+    reader: R,
+    read: &'a Cell<usize>,
+}
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.read.set(self.read.get() + n);
+        Ok(n)
+    }
+}
+fn eval_tokens_limited(tokens: impl Iterator<Item = Result<(Token, Span), String>>, scope: &Scope, limits: &EvalLimits) -> Result<f32, EvalError> {
+
+    let mut budget = Budget { limits, steps: 0, start: Instant::now() };
+
+    // the depth is checked here, to return it as a limit
+    let mut validator = Validator::with_max_depth(usize::MAX);
+    let mut implicit = Implicit::new();
+    let mut eval = StreamEvaluator::new();
+
+    let mut count = 0;
+    let mut depth = 0;
+    let mut calls = 0;
+
+    for t in tokens {
+        let (mut t, _) = t?;
+
+        count += 1;
+        if count > limits.max_tokens {
+            return Err(LimitExceeded::Tokens(limits.max_tokens).into());
+        }
+
+        validator.push(&t)?;
+
+        match t {
+            Token::Bracket(true) => {
+                depth += 1;
+                if depth > limits.max_depth {
+                    return Err(LimitExceeded::Depth(limits.max_depth).into());
+                }
+            },
+            Token::Bracket(false) => depth -= 1,
+            Token::Function(_) => {
+                calls += 1;
+                if calls > limits.max_function_calls {
+                    return Err(LimitExceeded::FunctionCalls(limits.max_function_calls).into());
+                }
+            },
+            _ => {},
+        }
+
+        budget.step()?;
+
+        if let Some(i) = implicit.before(&t) {
+            eval.push_checked(&i, &mut |i, stack| budget.check(i, stack))?;
+        }
+
+        resolve_variables(slice::from_mut(&mut t), scope)?;
+        eval.push_checked(&t, &mut |i, stack| budget.check(i, stack))?;
+    }
+
+    validator.finish()?;
+
+    Ok(eval.finish_checked(&mut |i, stack| budget.check(i, stack))?)
+}
+/// The steps and time used so far.
+struct Budget<'a> {
+    limits: &'a EvalLimits,
+    steps: usize,
+    start: Instant,
+}
+impl Budget<'_> {
+    fn step(&mut self) -> Result<(), LimitExceeded> {
+
+        self.steps += 1;
+
+        if self.steps > self.limits.max_steps {
+            return Err(LimitExceeded::Steps(self.limits.max_steps));
+        }
+
+        // reading the clock costs more than a step
+        if let Some(timeout) = self.limits.timeout {
+            if self.steps % 256 == 0 && self.start.elapsed() > timeout {
+                return Err(LimitExceeded::Timeout(timeout));
+            }
+        }
+        Ok(())
+    }
+    fn check(&mut self, i: &Instruction, stack: &[f32]) -> Result<(), LimitExceeded> {
+
+        if let Instruction::Operator(Operator::Pow) = i {
+            let exponent = stack[stack.len() - 1];
+
+            if exponent.abs() > self.limits.max_exponent {
+                return Err(LimitExceeded::Exponent(self.limits.max_exponent));
+            }
+        }
+
+        self.step()
+    }
+}
+
+#[test]
+fn limited_evaluation() {
+    let scope = Scope::new();
+    let limits = EvalLimits::default();
+
+    assert_eq!(eval_limited("2max(1, 3)^2 - pi", &scope, &limits), Ok(18.0 - std::f32::consts::PI));
+    assert_eq!(eval_limited("1 +", &scope, &limits), Err(EvalError::Invalid("Unexpected operator at the end".to_string())));
+
+    let long = " ".repeat(100_000) + "12";
+    assert_eq!(eval_limited(&long, &scope, &limits), Err(EvalError::Limit(LimitExceeded::InputLength(100_000))));
+    assert_eq!(eval_read_limited(long.as_bytes(), &scope, &limits), Err(EvalError::Limit(LimitExceeded::InputLength(100_000))));
+    assert_eq!(eval_read_limited(&long.as_bytes()[2..], &scope, &limits), Ok(12.0));
+    assert_eq!(eval_read_limited("1+".repeat(6000).as_bytes(), &scope, &limits), Err(EvalError::Limit(LimitExceeded::Tokens(10_000))));
+
+    let deep = format!("{}1{}", "(".repeat(101), ")".repeat(101));
+    assert_eq!(eval_limited(&deep, &scope, &limits), Err(EvalError::Limit(LimitExceeded::Depth(100))));
+
+    assert_eq!(eval_limited(&"abs(1)".repeat(1001), &scope, &limits), Err(EvalError::Limit(LimitExceeded::FunctionCalls(1000))));
+    assert_eq!(eval_limited("2^(10^4)", &scope, &limits), Err(EvalError::Limit(LimitExceeded::Exponent(1000.0))));
+
+    let limits = EvalLimits { max_steps: 10, ..Default::default() };
+    assert_eq!(eval_limited("1+2+3+4+5+6", &scope, &limits), Err(EvalError::Limit(LimitExceeded::Steps(10))));
+
+    let limits = EvalLimits { timeout: Some(Duration::ZERO), ..Default::default() };
+    assert_eq!(eval_limited(&"1+".repeat(1000), &scope, &limits), Err(EvalError::Limit(LimitExceeded::Timeout(Duration::ZERO))));
+}
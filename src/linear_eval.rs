@@ -1,177 +1,204 @@
-use std::usize;
-
-use crate::{token_parse::{token_to_string, Operator, Token}, DEBUG};
+use crate::{functions::{call_function, MAX_ARGUMENTS}, token_parse::{apply_operator, Operator, Token}};
 
 pub fn linear_eval(tokens: Vec<Token>) -> f32 {
-    let tokens = tokens.into_iter().map(|x| Some(x)).collect();
     let mut parser = Parser::new(tokens);
 
-    linear_eval_recursive(&mut parser, &mut 0, &mut 0)
-}
-
-fn linear_eval_recursive(tokens: &mut Parser, count: &mut usize, max: &mut usize) -> f32 {
+    let res = linear_eval_levels(&mut parser);
 
-    let mut adds = Vec::new();
-    let mut muls = Vec::new();
+    parser.number(res)
+}
+/// The operators of an open bracket, collected until it is closed.
+struct Level {
+    // the opening bracket, or the start of the list at the top level
+    open: usize,
+    // the slot before the current function argument
+    start: usize,
+    adds: Vec<(usize, Operator)>,
+    muls: Vec<(usize, Operator)>,
+    pows: Vec<(usize, Operator)>,
+    comparisons: Vec<(usize, Operator)>,
+}
+impl Level {
+    fn new(open: usize) -> Self {
+        Level { open, start: open, adds: Vec::new(), muls: Vec::new(), pows: Vec::new(), comparisons: Vec::new() }
+    }
+}
+/// Evaluates all tokens and returns the slot of the result. Open brackets are kept
+/// on a stack of levels, so any depth of nesting can be evaluated.
+fn linear_eval_levels(tokens: &mut Parser) -> usize {
 
-    
+    let mut levels = vec![Level::new(tokens.index)];
 
     while tokens.has_next() {
-        match tokens.consume() {
-            Some(s) => match s {
-                Token::Number(_) => {},
-                Token::Operator(operator) => {
-                    match operator {
-                        Operator::Plus => adds.push((tokens.index, operator)),
-                        Operator::Minus => adds.push((tokens.index, operator)),
-                        Operator::Mul => muls.push((tokens.index, operator)),
-                        Operator::Div => muls.push((tokens.index, operator)),
-                    }
-                },
-                Token::Bracket(b) => {
-                    if b {
-                        let ind = tokens.index;
-                        linear_eval_recursive(tokens, count, max);
-                        tokens.tokens[ind] = None;
-                        
-                        if DEBUG { print(&tokens.tokens); }
-                        else {
-                            //tokens.tokens = tokens.tokens.drain(..).filter(|x| x.is_some()).collect(); 
-                        } 
-                        tokens.index = ind;
-                    } 
-                    else {
-                        tokens.tokens[tokens.index] = None;
-                        break;
-                    }
-                },
+        let index = tokens.consume();
+        let level = levels.last_mut().unwrap();
+
+        match &tokens.tokens[index] {
+            Token::Number(_) => {},
+            Token::Function(_) => {},
+            Token::Variable(_) => unreachable!("variables are resolved before evaluation"),
+            Token::List(_) => unreachable!("lists are validated"),
+            Token::Str(_) => unreachable!("strings are validated"),
+            Token::Comma => {
+                // every argument is evaluated on its own
+                tokens.evaluate_segment(level);
+
+                level.adds.clear();
+                level.muls.clear();
+                level.pows.clear();
+                level.comparisons.clear();
+
+                level.start = index;
+            },
+            Token::Operator(operator) => {
+                match operator {
+                    Operator::Plus => level.adds.push((index, *operator)),
+                    Operator::Minus => level.adds.push((index, *operator)),
+                    Operator::Mul | Operator::MatMul => level.muls.push((index, *operator)),
+                    Operator::Div => level.muls.push((index, *operator)),
+                    Operator::Pow => level.pows.push((index, *operator)),
+                    Operator::Equal | Operator::NotEqual | Operator::Less | Operator::LessEqual | Operator::Greater | Operator::GreaterEqual => {
+                        level.comparisons.push((index, *operator));
+                    },
+                }
+            },
+            Token::Bracket(b) => {
+                if *b {
+                    levels.push(Level::new(index));
+                    continue;
+                }
+
+                let level = levels.pop().unwrap();
+                tokens.evaluate_segment(&level);
+
+                let open = level.open;
+                let res = tokens.next[level.start];
+
+                // continue after the closing bracket
+                tokens.index = if let Some(Token::Function(_)) = tokens.tokens.get(tokens.prev[open]) {
+                    tokens.call_function(tokens.prev[open], index)
+                }
+                else {
+                    tokens.remove(open);
+                    tokens.remove(index);
+                    res
+                };
             },
-            None => {},
         }
     }
 
-    for mul in muls.iter() {
-        tokens.evaluate_operator(mul.0, mul.1);
-    }
-
-    for add in adds.iter() {
-        tokens.evaluate_operator(add.0, add.1);
-    }
+    let level = levels.pop().unwrap();
+    tokens.evaluate_segment(&level);
 
-    let last = 
-    if let Some(n) = adds.last()      { n.0 }
-    else if let Some(n) = muls.last() { n.0 }
-    else                                                  { 0   };
-
-    let n = if let Token::Number(n) = tokens.tokens[last].as_ref().unwrap() {
-        *n
-    }
-    else {
-        unreachable!();
-    };
-
-    *count += muls.len() + adds.len();
-    
-    //let len = tokens.tokens.len();
-
-    if *count > 10_000 && (*max - tokens.index) > 10_000  {
-        filter_vec(&mut tokens.tokens, tokens.index + 1, *max);
-        *count = 0;
-        *max = 0;
-    }
-
-    *max = tokens.index.max(*max);
-
-    return n;    
+    tokens.next[level.start]
 }
+/// The tokens as a circular doubly linked list, so the neighbours of every slot can be found
+/// immediately while evaluated slots are removed. The last slot is the start of the list.
 struct Parser {
-    tokens: Vec<Option<Token>>,
+    tokens: Vec<Token>,
+    prev: Vec<usize>,
+    next: Vec<usize>,
     index: usize,
 }
 impl Parser {
-    fn new(tokens: Vec<Option<Token>>) -> Self {
-        Parser { tokens, index: usize::MAX }
+    fn new(tokens: Vec<Token>) -> Self {
+        let len = tokens.len() + 1;
+
+        let prev = (0..len).map(|i| (i + len - 1) % len).collect();
+        let next = (0..len).map(|i| (i + 1) % len).collect();
+
+        Parser { tokens, prev, next, index: len - 1 }
     }
-    fn consume(&mut self) -> Option<Token> {
-        self.index = self.index.wrapping_add(1);
-        self.tokens[self.index].clone()
+    fn consume(&mut self) -> usize {
+        self.index = self.next[self.index];
+        self.index
     }
     fn has_next(&self) -> bool {
-        self.tokens.len() > self.index.wrapping_add(1)
+        self.next[self.index] != self.tokens.len()
     }
-    fn evaluate_operator(&mut self, index: usize, o: Operator) {
+    fn number(&self, index: usize) -> f32 {
+        match self.tokens[index] {
+            Token::Number(n) => n,
+            _ => unreachable!("should always be a number"),
+        }
+    }
+    fn remove(&mut self, index: usize) {
+        let (prev, next) = (self.prev[index], self.next[index]);
 
-        let (i1, left_opt) = self.tokens[..index].iter()
-            .rev()
-            .enumerate()
-            .find_map(|x| x.1.clone().map(|a| (x.0, a)))
-            .unwrap();
-
-        let (i2, right_opt) = self.tokens[index + 1..].iter()
-            .enumerate()
-            .find_map(|x| x.1.clone().map(|a| (x.0, a)))
-            .unwrap();
-
-        let (left, right) = match (left_opt, right_opt) {
-            (Token::Number(n1), Token::Number(n2)) => (n1, n2),
-            _ => panic!("should always be numbers"),
-        };
-
-        let res = match o {
-            Operator::Plus => left + right,
-            Operator::Minus => left - right,
-            Operator::Mul => left * right,
-            Operator::Div => left / right,
-        };
-
-        self.tokens[index] = Some(Token::Number(res));
-
-        self.tokens[index - i1 - 1] = None;
-        self.tokens[index + i2 + 1] = None;
+        self.next[prev] = next;
+        self.prev[next] = prev;
     }
-}
+    /// Evaluates the operators of a bracket or function argument, leaving only the result in the list.
+    fn evaluate_segment(&mut self, level: &Level) {
 
-fn print(tokens: &[Option<Token>]) {
-    print!("Tokens: ");
-    for t in tokens {
-        match t {
-            Some(t2) => print!("{} ", token_to_string(t2)),
-            None => print!("_ "),
+        // exponentiation is right associative
+        for pow in level.pows.iter().rev() {
+            self.evaluate_operator(pow.0, pow.1);
+        }
+
+        for mul in level.muls.iter() {
+            self.evaluate_operator(mul.0, mul.1);
+        }
+
+        for add in level.adds.iter() {
+            self.evaluate_operator(add.0, add.1);
+        }
+
+        for comparison in level.comparisons.iter() {
+            self.evaluate_operator(comparison.0, comparison.1);
         }
     }
-    println!("");
-}
-fn filter_vec(vec: &mut Vec<Option<Token>>, start: usize, end: usize) {
-    let mut a = start;
-    let mut b = start;
-
-    while a < end {
-        if vec[a].is_some() {
-            vec.swap(a, b);
-            b += 1;
+    /// Replaces the function at `index` with its result and removes its brackets and arguments.
+    fn call_function(&mut self, index: usize, close: usize) -> usize {
+
+        let mut args = [0.0; MAX_ARGUMENTS];
+        let mut count = 0;
+        let mut arg = self.next[index];
+
+        while arg != close {
+            if let Token::Number(n) = self.tokens[arg] {
+                args[count] = n;
+                count += 1;
+            }
+            arg = self.next[arg];
+        }
+
+        if let Token::Function(f) = self.tokens[index] {
+            self.tokens[index] = Token::Number(call_function(&f, &args[..count]));
         }
-        a += 1;
+
+        let next = self.next[close];
+        self.next[index] = next;
+        self.prev[next] = index;
+
+        index
     }
+    fn evaluate_operator(&mut self, index: usize, o: Operator) {
+
+        let (left, right) = (self.prev[index], self.next[index]);
 
-    vec.copy_within(end.., b);
+        self.tokens[index] = Token::Number(apply_operator(&o, self.number(left), self.number(right)));
 
-    vec.truncate(b + vec.len() - end);
+        self.remove(left);
+        self.remove(right);
+    }
 }
 #[test]
-fn testtt() {
-    let mut vec = vec![ 
-        None, None, None, Some(Token::Number(3f32)), None, 
-        None, Some(Token::Number(3f32)), Some(Token::Number(3f32)), Some(Token::Number(3f32)), Some(Token::Number(3f32)), 
-        None, None, None ];
-
-    filter_vec(&mut vec, 5, 11);
-
-    for n in vec {
-        match n {
-            Some(a) => println!("{}", token_to_string(&a)),
-            None => println!("None"),
-        }
-    }
+fn linked_list_evaluation() {
+    use crate::prepare_tokens;
+
+    let mut parser = Parser::new(prepare_tokens("2 * (3 + 4)").unwrap());
 
-    panic!("heh");
-}
\ No newline at end of file
+    parser.remove(1);
+    parser.remove(0);
+
+    assert_eq!(parser.next[parser.tokens.len()], 2);
+    assert_eq!(parser.prev[2], parser.tokens.len());
+
+    let res = linear_eval_levels(&mut parser);
+    assert_eq!(parser.number(res), 7.0);
+
+    for (exp, expected) in [("((((1 + 2)) * 3))", 9.0), ("2max(1 - 2, -(3)) + 1", -1.0)] {
+        assert_eq!(linear_eval(prepare_tokens(exp).unwrap()), expected, "{}", exp);
+    }
+}
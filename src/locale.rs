@@ -0,0 +1,197 @@
+/// The characters numbers and argument lists are written with. All of them have to be ASCII
+/// and different from each other and from the operators.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NumberFormat {
+    pub decimal: char,
+    /// Separates groups of three digits before the decimal separator, as in `1,000.5`.
+    pub thousands: Option<char>,
+    /// Separates the arguments of a function.
+    pub argument: char,
+}
+impl NumberFormat {
+    /// `1.000,5` and `max(1,5; 2)`, as written in most of Europe.
+    pub fn decimal_comma() -> Self {
+        NumberFormat { decimal: ',', thousands: Some('.'), argument: ';' }
+    }
+}
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat { decimal: '.', thousands: None, argument: ',' }
+    }
+}
+/// How `format_result` writes a number. The digits of `Significant`, `Scientific` and `Engineering`
+/// are significant digits, those of `Fixed` are decimals.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Notation {
+    /// The shortest decimal that reads back as the same number, like `Display`.
+    #[default]
+    Shortest,
+    /// `3.14`
+    Fixed(usize),
+    /// `3.142`, small and large numbers are written in scientific notation with trailing zeros left out.
+    Significant(usize),
+    /// `3.142e3`
+    Scientific(usize),
+    /// `31.42e3`, the exponent is a multiple of three.
+    Engineering(usize),
+}
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResultFormat {
+    pub notation: Notation,
+    /// Separates groups of three digits before the decimal separator with the thousands separator,
+    /// or with `,` or `.`, whichever isn't the decimal separator.
+    pub grouping: bool,
+}
+/// Prints the number like `Display` does, with the separators of the format.
+pub fn format_number(n: f32, format: &NumberFormat) -> String {
+    format_result(n, &ResultFormat { notation: Notation::Shortest, grouping: format.thousands.is_some() }, format)
+}
+/// Prints the number in the notation of `result`, with the separators of `format`.
+/// Exponents are written as `e3` and `e-3`, as the tokenizer reads them.
+pub fn format_result(n: f32, result: &ResultFormat, format: &NumberFormat) -> String {
+
+    if !n.is_finite() {
+        return n.to_string();
+    }
+
+    // rounded in double precision, so the digits of the float are what gets rounded
+    let x = n as f64;
+
+    let s = match result.notation {
+        Notation::Shortest => n.to_string(),
+        Notation::Fixed(decimals) => format!("{:.*}", decimals, x),
+        Notation::Significant(digits) => {
+            let digits = digits.max(1);
+            let (mantissa, exponent) = scientific(x, digits);
+
+            if exponent < -4 || exponent >= digits as i32 {
+                format!("{}e{}", trim_zeros(&mantissa), exponent)
+            }
+            else {
+                trim_zeros(&format!("{:.*}", (digits as i32 - 1 - exponent) as usize, x)).to_string()
+            }
+        },
+        Notation::Scientific(digits) => {
+            let (mantissa, exponent) = scientific(x, digits.max(1));
+            format!("{}e{}", mantissa, exponent)
+        },
+        Notation::Engineering(digits) => {
+            let digits = digits.max(1);
+            let (mantissa, exponent) = scientific(x, digits);
+
+            // moves the decimal separator right until the exponent is a multiple of three
+            let shift = exponent.rem_euclid(3);
+            let mantissa: f64 = mantissa.parse::<f64>().unwrap() * 10f64.powi(shift);
+
+            format!("{:.*}e{}", digits.saturating_sub(1 + shift as usize), mantissa, exponent - shift)
+        },
+    };
+
+    let grouping = match (result.grouping, format.thousands) {
+        (false, _) => None,
+        (true, Some(t)) => Some(t),
+        (true, None) => Some(if format.decimal == ',' { '.' } else { ',' }),
+    };
+
+    localize(&s, format.decimal, grouping)
+}
+/// The mantissa and exponent of `x` rounded to `digits` significant digits.
+fn scientific(x: f64, digits: usize) -> (String, i32) {
+
+    let s = format!("{:.*e}", digits - 1, x);
+    let (mantissa, exponent) = s.split_once('e').unwrap();
+
+    (mantissa.to_string(), exponent.parse().unwrap())
+}
+fn trim_zeros(s: &str) -> &str {
+    if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { s }
+}
+/// Replaces the `.` of a printed number and groups the digits before it.
+fn localize(s: &str, decimal: char, grouping: Option<char>) -> String {
+
+    let (sign, s) = s.split_at(if s.starts_with('-') { 1 } else { 0 });
+    let integer = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+    let mut res = sign.to_string();
+
+    for (i, c) in s[..integer].chars().enumerate() {
+        if let Some(t) = grouping {
+            if i > 0 && (integer - i) % 3 == 0 {
+                res.push(t);
+            }
+        }
+        res.push(c);
+    }
+
+    for c in s[integer..].chars() {
+        res.push(if c == '.' { decimal } else { c });
+    }
+    res
+}
+
+#[test]
+fn number_formats() {
+    use crate::{eval_exp_with_format, script::{eval_script_with_format, Scope}};
+
+    let comma = NumberFormat::decimal_comma();
+
+    for (exp, n) in [("3,5 * 2", 7.0), ("1.000,5 + max(1,5; 2)", 1002.5), (",5 + 1.000.000", 1000000.5), ("1_000,5", 1000.5), ("2e3 - 1,5e1", 1985.0)] {
+        assert_eq!(eval_exp_with_format(exp, &Scope::new(), &comma), Ok(n), "{}", exp);
+    }
+
+    assert_eq!(eval_exp_with_format("1.5", &Scope::new(), &comma), Err("Invalid number format: \"1.5\"".to_string()));
+    assert_eq!(eval_exp_with_format("max(1, 2)", &Scope::new(), &comma), Err("Unexpected number after another number".to_string()));
+    assert_eq!(eval_exp_with_format("1;2", &Scope::new(), &NumberFormat::default()), Err("Unexpected character: \";\"".to_string()));
+
+    let swiss = NumberFormat { thousands: Some('\''), ..NumberFormat::default() };
+    assert_eq!(eval_exp_with_format("1'234.5 * 2", &Scope::new(), &swiss), Ok(2469.0));
+    assert_eq!(eval_exp_with_format("12'34", &Scope::new(), &swiss), Err("Invalid number format: \"12'34\"".to_string()));
+
+    let read = crate::eval_tokens(crate::token_parse::ReadTokens::with_format("1.000,5 * 2".as_bytes(), &comma), &Scope::new());
+    assert_eq!(read, Ok(2001.0));
+
+    // the argument separator isn't read as the end of a statement
+    assert_eq!(eval_script_with_format("let a = 2,5; max(a; 1) * 2", &mut Scope::new(), &comma), Ok(5.0));
+
+    assert_eq!(format_number(-1234567.5, &comma), "-1.234.567,5");
+    assert_eq!(format_number(123.25, &comma), "123,25");
+    assert_eq!(format_number(1234.0, &NumberFormat::default()), "1234");
+    assert_eq!(format_number(f32::NEG_INFINITY, &comma), "-inf");
+}
+#[test]
+fn result_formats() {
+    use crate::eval_exp;
+
+    let en = NumberFormat::default();
+
+    for (n, notation, s) in [
+        (1.0 / 3.0 * 3.0 - 0.9, Notation::Shortest, "0.100000024"),
+        (1.0 / 3.0 * 3.0 - 0.9, Notation::Significant(7), "0.1"),
+        (2.0 / 3.0, Notation::Fixed(2), "0.67"),
+        (1234.5, Notation::Fixed(0), "1234"),
+        (1234.5678, Notation::Significant(3), "1.23e3"),
+        (1234.5678, Notation::Significant(6), "1234.57"),
+        (0.000012345, Notation::Significant(3), "1.23e-5"),
+        (6.02e23, Notation::Significant(4), "6.02e23"),
+        (-1234.5, Notation::Scientific(4), "-1.234e3"),
+        (9.9996, Notation::Scientific(4), "1.000e1"),
+        (0.0, Notation::Scientific(2), "0.0e0"),
+        (31415.9, Notation::Engineering(4), "31.42e3"),
+        (0.00042, Notation::Engineering(3), "420e-6"),
+        (999.96, Notation::Engineering(4), "1.000e3"),
+        (-5.0, Notation::Engineering(1), "-5e0"),
+    ] {
+        let s2 = format_result(n, &ResultFormat { notation, grouping: false }, &en);
+        assert_eq!(s2, s, "{} {:?}", n, notation);
+
+        // what is printed can be read back, without rounding as the same number
+        let read = eval_exp(&s2).unwrap();
+        assert!(notation != Notation::Shortest || read == n, "{}", s2);
+    }
+
+    let grouped = ResultFormat { notation: Notation::Fixed(2), grouping: true };
+    assert_eq!(format_result(-123456.25, &grouped, &en), "-123,456.25");
+    assert_eq!(format_result(123456.25, &grouped, &NumberFormat::decimal_comma()), "123.456,25");
+    assert_eq!(format_result(1234.5, &ResultFormat { notation: Notation::Scientific(3), grouping: true }, &NumberFormat::decimal_comma()), "1,23e3");
+    assert_eq!(format_result(f32::NAN, &grouped, &en), "NaN");
+}
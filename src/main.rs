@@ -1,15 +1,11 @@
-use std::{fs::{self, File}, io::{self, Write}, path::Path, ptr::null, time::Instant};
-use linear_eval::linear_eval;
+use std::{fs, io, path::Path, time::Instant};
+use exp_parser::{bytecode::evaluate_bytecode, complex::{eval_complex, format_polar}, eval_read, linear_eval::linear_eval, parallel::{parallel_eval, ParallelOptions}, prepare_tokens, locale::{format_result, Notation, NumberFormat, ResultFormat}, script::{eval_script_with_format, Scope}, units::eval_units, interval::eval_interval, matrix::{eval_matrix, Value}, dates::{eval_dates, TimeValue}, strings::{eval_strings, TextValue}, token_tree::evaluate_exp};
 use rand::{thread_rng, Rng};
-use token_parse::{parse_exp, token_to_string, Token};
-use validation::{add_implicit_tokens, validate_tokens};
 
-mod token_parse;
-mod validation;
-mod token_tree;
-mod linear_eval;
+mod scanning;
+
+use scanning::linear_eval_scanning;
 
-const DEBUG: bool = cfg!(debug_assertions);
 // -.5(1+2)(-3+4) * 5 + 3 * 2(1*2_0)
 // (1 + 2 * 3) * 4 (1 + 2 + 3) * 5
 fn main() {
@@ -34,59 +30,150 @@ fn main() {
 
     //println!("rr {}", eval_exp("-.5(1+2)(-3+4) * 5 + 3 * 2(1*2_0)").unwrap());
 
-    loop {
-        let input = get_input("Input expression...");
+    let mut scope = Scope::new();
+    let mut format = NumberFormat::default();
+    let mut result_format = ResultFormat { notation: Notation::Shortest, grouping: false };
+    let mut mode = Mode::Real;
 
-        match eval_exp(&input) {
-            Ok(result) => println!("result: {}", result),
-            Err(err) => println!("{}", err),
+    while let Some(input) = get_input("Input expression...") {
+
+        if let Some(args) = input.strip_prefix(":fmt") {
+            if let Err(err) = set_result_format(args, &mut result_format) {
+                println!("{}", err);
+            }
+            continue;
         }
-    } 
-}
-fn eval_exp(input: &str) -> Result<f32, String> {
 
-    let mut tokens = match parse_exp(input) {
-        Ok(e) => e,
-        Err(s) => {
-            if s.len() == 1 {
-                return Err(format!("Unexpected character: \"{}\"", s));
+        if let Some(locale) = input.strip_prefix(":locale") {
+            // applies to the input in real mode and to results that are a single real number
+            match locale.trim() {
+                "en" => format = NumberFormat::default(),
+                "eu" => format = NumberFormat::decimal_comma(),
+                locale => println!("Unknown locale: \"{}\", expected en or eu", locale),
             }
-            else {
-                return Err(format!("Invalid number format: \"{}\"", s));
+            continue;
+        }
+
+        if let Some(name) = input.strip_prefix(":mode") {
+            match name.trim() {
+                "real" => mode = Mode::Real,
+                "complex" => mode = Mode::Complex,
+                "polar" => mode = Mode::Polar,
+                "units" => mode = Mode::Units,
+                "interval" => mode = Mode::Interval,
+                "matrix" => mode = Mode::Matrix,
+                "dates" => mode = Mode::Dates,
+                "strings" => mode = Mode::Strings,
+                name => println!("Unknown mode: \"{}\", expected real, complex, polar, units, interval, matrix, dates or strings", name),
             }
-        },
-    };
+            continue;
+        }
+
+        if mode == Mode::Units {
+            match eval_units(&input, &scope) {
+                Ok(m) => println!("result: {}", format!("{} {}", format_result(m.value, &result_format, &format), m.unit).trim_end()),
+                Err(err) => println!("{}", err),
+            }
+            continue;
+        }
 
-    if DEBUG {
-        print!("Raw tokens:");
-        for t in tokens.iter() {
-            print!(" {}", token_to_string(t));
+        // the bounds are printed in full, rounding them could leave out the result
+        if mode == Mode::Interval {
+            match eval_interval(&input, &scope) {
+                Ok(i) => println!("result: {}", i),
+                Err(err) => println!("{}", err),
+            }
+            continue;
         }
-        println!("");
-    }
 
-    validate_tokens(&tokens)?;
+        if mode == Mode::Matrix {
+            match eval_matrix(&input, &scope) {
+                Ok(Value::Number(n)) => println!("result: {}", format_result(n, &result_format, &format)),
+                Ok(value) => println!("result: {}", value),
+                Err(err) => println!("{}", err),
+            }
+            continue;
+        }
 
-    add_implicit_tokens(&mut tokens);
+        if mode == Mode::Dates {
+            match eval_dates(&input, &scope) {
+                Ok(TimeValue::Number(n)) => println!("result: {}", format_result(n as f32, &result_format, &format)),
+                Ok(value) => println!("result: {}", value),
+                Err(err) => println!("{}", err),
+            }
+            continue;
+        }
 
-    if DEBUG {
-        print!("Implicit tokens:");
-        for t in tokens.iter() {
-            print!(" {}", token_to_string(t));
+        if mode == Mode::Strings {
+            match eval_strings(&input, &scope) {
+                Ok(TextValue::Number(n)) => println!("result: {}", format_result(n, &result_format, &format)),
+                Ok(value) => println!("result: {}", value),
+                Err(err) => println!("{}", err),
+            }
+            continue;
+        }
+
+        if mode != Mode::Real {
+            match eval_complex(&input, &scope) {
+                Ok(z) if mode == Mode::Polar => println!("result: {}", format_polar(z)),
+                Ok(z) => println!("result: {}", z),
+                Err(err) => println!("{}", err),
+            }
+            continue;
         }
-        println!("");
-    }
 
-    Ok(linear_eval(tokens))
+        match eval_script_with_format(&input, &mut scope, &format) {
+            Ok(result) => println!("result: {}", format_result(result, &result_format, &format)),
+            Err(err) => println!("{}", err),
+        }
+    } 
+}
+/// How the REPL evaluates and prints, complex results are printed as `a + bi` or in polar form.
+#[derive(PartialEq)]
+enum Mode {
+    Real,
+    Complex,
+    Polar,
+    Units,
+    Interval,
+    Matrix,
+    Dates,
+    Strings,
 }
-fn get_input(prompt: &str) -> String {
+/// Reads a line, `None` at the end of the input.
+fn get_input(prompt: &str) -> Option<String> {
     println!("{}", prompt);
 
     let mut buf = String::new();
 
-    io::stdin().read_line(&mut buf).unwrap();
-
-    return buf.trim().to_string();
+    match io::stdin().read_line(&mut buf).unwrap() {
+        0 => None,
+        _ => Some(buf.trim().to_string()),
+    }
+}
+/// Handles `:fmt plain`, `:fmt fixed|sig|sci|eng <digits>` and `:fmt group on|off`. Only results that
+/// are a single real number are formatted, complex numbers, intervals, matrices, dates and strings are printed with `Display`.
+fn set_result_format(args: &str, result: &mut ResultFormat) -> Result<(), String> {
+
+    let words: Vec<&str> = args.split_whitespace().collect();
+
+    match words[..] {
+        ["plain"] => result.notation = Notation::Shortest,
+        ["group", "on"] => result.grouping = true,
+        ["group", "off"] => result.grouping = false,
+        [notation @ ("fixed" | "sig" | "sci" | "eng"), digits] => {
+            let digits = digits.parse().map_err(|_| format!("Invalid number of digits: \"{}\"", digits))?;
+
+            result.notation = match notation {
+                "fixed" => Notation::Fixed(digits),
+                "sig" => Notation::Significant(digits),
+                "sci" => Notation::Scientific(digits),
+                _ => Notation::Engineering(digits),
+            };
+        },
+        _ => return Err("Expected :fmt plain, :fmt fixed|sig|sci|eng <digits> or :fmt group on|off. The format applies to results that are a single real number, other values are printed in full".to_string()),
+    }
+    Ok(())
 }
 fn measure() {
 
@@ -96,9 +183,33 @@ fn measure() {
 
     let ins = Instant::now();
 
-    let r = eval_exp(&s).unwrap();
+    let tokens = prepare_tokens(&s).unwrap();
 
-    println!("{:?}", ins.elapsed());
+    println!("Parsing: {:?}", ins.elapsed());
+
+    measure_eval("Linear", || linear_eval(tokens.clone()));
+    measure_eval("Tree", || evaluate_exp(&tokens));
+    measure_eval("Bytecode", || evaluate_bytecode(&tokens));
+    measure_eval("Parallel", || parallel_eval(&tokens, &ParallelOptions::default()));
+
+    // parses and evaluates the file while it is read
+    measure_eval("Streaming", || eval_read(fs::File::open("./input.txt").unwrap(), &Scope::new()).unwrap());
+
+    // deeply nested brackets, every level is evaluated on its own
+    let tokens = prepare_tokens(&format!("{}1{}", "(".repeat(5000), "+1)".repeat(5000))).unwrap();
+
+    // before and after linear_eval kept its tokens in a linked list
+    measure_eval("Linear nested (scanning)", || linear_eval_scanning(tokens.clone()));
+    measure_eval("Linear nested", || linear_eval(tokens.clone()));
+    measure_eval("Bytecode nested", || evaluate_bytecode(&tokens));
+}
+fn measure_eval(name: &str, eval: impl FnOnce() -> f32) {
+
+    let ins = Instant::now();
+
+    let r = eval();
+
+    println!("{}: {:?}", name, ins.elapsed());
     println!("Result: {}", r);
 }
 fn generate_test() {
@@ -109,25 +220,21 @@ fn generate_test() {
 
     let mut res = String::new();
 
-    let operators = vec!['+', '-', '*', '/'];
+    let operators = ['+', '-', '*', '/'];
     let mut brackets = 0;
 
     for _ in 0..1_000_000 {
 
-        if thread_rng().gen_range(0..8) == 0 {
-            if brackets < 30 {
-                res.push('(');
+        if thread_rng().gen_range(0..8) == 0 && brackets < 30 {
+            res.push('(');
             brackets += 1;
-            }
         }
 
         res.push_str(&(thread_rng().gen_range(0..10000) as f32 / 100f32).to_string());
 
-        if thread_rng().gen_range(0..8) == 0 {
-            if brackets > 0 {
-                res.push(')');
-                brackets -= 1;
-            }
+        if thread_rng().gen_range(0..8) == 0 && brackets > 0 {
+            res.push(')');
+            brackets -= 1;
         }
 
         res.push(operators[thread_rng().gen_range(0..4)]);
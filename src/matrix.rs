@@ -0,0 +1,304 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{ast::{ast_from_list_tokens, check_call, Expr, ExprKind, UnaryOp}, functions::{call_function, function_to_string, Function}, script::Scope, token_parse::{apply_operator, is_comparison, operator_to_string, parse_exp_spans, Operator, Span}, validation::{Validator, COMPARISONS, STRINGS}};
+
+/// The value of an expression with lists. Lists only exist in trees, tokens have no value that
+/// holds one, so the token evaluators and every other evaluator reject them on purpose.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(f32),
+    Vector(Vec<f32>),
+    /// A list of rows of the same length.
+    Matrix(Vec<Vec<f32>>),
+}
+impl Value {
+    /// `[3]` for a vector of three numbers, `[2x3]` for a matrix of two rows of three.
+    pub fn shape(&self) -> String {
+        match self {
+            Value::Number(_) => "a number".to_string(),
+            Value::Vector(v) => format!("[{}]", v.len()),
+            Value::Matrix(m) => format!("[{}x{}]", m.len(), m[0].len()),
+        }
+    }
+    fn map(self, f: impl Fn(f32) -> f32) -> Value {
+        match self {
+            Value::Number(n) => Value::Number(f(n)),
+            Value::Vector(v) => Value::Vector(v.into_iter().map(f).collect()),
+            Value::Matrix(m) => Value::Matrix(m.into_iter().map(|row| row.into_iter().map(&f).collect()).collect()),
+        }
+    }
+}
+/// Prints numbers like `Display` and lists as they are written, `[[1, 2], [3, 4]]`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Vector(v) => write_list(f, v),
+            Value::Matrix(m) => {
+                write!(f, "[")?;
+                for (i, row) in m.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write_list(f, row)?;
+                }
+                write!(f, "]")
+            },
+        }
+    }
+}
+fn write_list(f: &mut fmt::Formatter, v: &[f32]) -> fmt::Result {
+    write!(f, "[")?;
+    for (i, n) in v.iter().enumerate() {
+        if i > 0 { write!(f, ", ")?; }
+        write!(f, "{}", n)?;
+    }
+    write!(f, "]")
+}
+/// Evaluates the input with vectors like `[1, 2]` and matrices like `[[1, 2], [3, 4]]`.
+///
+/// `+ - * / ^`, functions of numbers and `min`/`max` work elementwise. A number is applied to every
+/// element and a vector to every row of a matrix, other shapes have to be the same. `@` is the
+/// matrix product, `dot`, `transpose` and `det` take vectors and matrices. Variables of the scope are numbers,
+/// see `eval_matrix_with` for variables that hold a vector or matrix.
+pub fn eval_matrix(input: &str, scope: &Scope) -> Result<Value, String> {
+    evaluate_matrix(&parse_matrix_ast(input)?, scope)
+}
+/// Like `eval_matrix`, a variable in `values` is bound to its value instead of a number of the scope.
+pub fn eval_matrix_with(input: &str, scope: &Scope, values: &HashMap<String, Value>) -> Result<Value, String> {
+    evaluate_matrix_with(&parse_matrix_ast(input)?, scope, values)
+}
+/// Like `parse_ast`, lists are allowed.
+pub fn parse_matrix_ast(input: &str) -> Result<Expr, String> {
+    ast_from_list_tokens(parse_exp_spans(input)?)
+}
+/// Evaluates the tree, an error about shapes ends with the span of the expression it comes from.
+pub fn evaluate_matrix(expr: &Expr, scope: &Scope) -> Result<Value, String> {
+    evaluate_matrix_with(expr, scope, &HashMap::new())
+}
+/// Like `evaluate_matrix`, see `eval_matrix_with`.
+pub fn evaluate_matrix_with(expr: &Expr, scope: &Scope, values: &HashMap<String, Value>) -> Result<Value, String> {
+    expr.try_fold(|expr, children: Vec<Value>| {
+        let mut children = children.into_iter();
+
+        match &expr.kind {
+            ExprKind::Num(n) => Ok(Value::Number(*n)),
+            ExprKind::Var(name) => match values.get(name) {
+                Some(value) => Ok(value.clone()),
+                None => scope.get(name).map(Value::Number).ok_or_else(|| format!("Unknown variable: \"{}\"", name)),
+            },
+            ExprKind::Unary(UnaryOp::Neg, _) => Ok(children.next().unwrap().map(|x| -x)),
+            ExprKind::Binary(o, ..) if is_comparison(o) => Err(COMPARISONS.to_string()),
+            ExprKind::Binary(o, ..) => {
+                let (a, b) = (children.next().unwrap(), children.next().unwrap());
+                let shapes = (a.shape(), b.shape());
+
+                let res = match o {
+                    Operator::MatMul => mat_mul(a, b),
+                    _ => elementwise(a, b, |x, y| apply_operator(o, x, y)),
+                };
+                res.ok_or_else(|| mismatch(shapes, operator_to_string(o), &expr.span))
+            },
+            ExprKind::Call(f, args) => {
+                check_call(f, args, &Validator::with_lists())?;
+
+                let a = children.next().unwrap();
+                let name = function_to_string(f);
+
+                match f {
+                    Function::Min | Function::Max | Function::Dot => {
+                        let b = children.next().unwrap();
+                        let shapes = (a.shape(), b.shape());
+
+                        let res = match f {
+                            Function::Dot => dot(a, b),
+                            _ => elementwise(a, b, |x, y| call_function(f, &[x, y])),
+                        };
+                        res.ok_or_else(|| mismatch(shapes, name, &expr.span))
+                    },
+                    Function::Transpose => Ok(transpose(a)),
+                    Function::Det => match a {
+                        Value::Number(n) => Ok(Value::Number(n)),
+                        Value::Matrix(m) if m.len() == m[0].len() => Ok(Value::Number(det(m))),
+                        a => Err(format!("det needs a square matrix, not {} at {}", a.shape(), span_to_string(&expr.span))),
+                    },
+                    _ => Ok(a.map(|x| call_function(f, &[x]))),
+                }
+            },
+            ExprKind::List(_) => list(children.collect(), &expr.span),
+            ExprKind::Str(_) => Err(STRINGS.to_string()),
+        }
+    })
+}
+fn span_to_string(span: &Span) -> String {
+    format!("{}..{}", span.start, span.end)
+}
+fn mismatch((a, b): (String, String), what: &str, span: &Span) -> String {
+    format!("Shapes {} and {} don't match for \"{}\" at {}", a, b, what, span_to_string(span))
+}
+/// A vector of numbers or a matrix of vectors of the same length.
+fn list(values: Vec<Value>, span: &Span) -> Result<Value, String> {
+
+    if values.iter().all(|v| matches!(v, Value::Number(_))) {
+        return Ok(Value::Vector(values.into_iter().map(|v| match v { Value::Number(n) => n, _ => unreachable!() }).collect()));
+    }
+
+    let mut rows = Vec::with_capacity(values.len());
+
+    for v in values {
+        match v {
+            Value::Vector(row) if rows.first().is_none_or(|first: &Vec<f32>| first.len() == row.len()) => rows.push(row),
+            Value::Vector(row) => {
+                return Err(format!("The rows of a matrix need the same length, not {} and {} at {}", rows[0].len(), row.len(), span_to_string(span)));
+            },
+            Value::Number(_) => return Err(format!("Expected a row of a matrix, not a number at {}", span_to_string(span))),
+            Value::Matrix(_) => return Err(format!("Lists can only hold numbers and rows at {}", span_to_string(span))),
+        }
+    }
+    Ok(Value::Matrix(rows))
+}
+fn zip(u: &[f32], v: &[f32], f: impl Fn(f32, f32) -> f32) -> Vec<f32> {
+    u.iter().zip(v).map(|(x, y)| f(*x, *y)).collect()
+}
+/// Applies `f` to the elements at the same place, `None` if the shapes don't match.
+fn elementwise(a: Value, b: Value, f: impl Fn(f32, f32) -> f32) -> Option<Value> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => Some(Value::Number(f(x, y))),
+        (Value::Number(x), b) => Some(b.map(|y| f(x, y))),
+        (a, Value::Number(y)) => Some(a.map(|x| f(x, y))),
+        (Value::Vector(u), Value::Vector(v)) if u.len() == v.len() => Some(Value::Vector(zip(&u, &v, f))),
+        (Value::Matrix(m), Value::Matrix(n)) if m.len() == n.len() && m[0].len() == n[0].len() => {
+            Some(Value::Matrix(m.iter().zip(&n).map(|(u, v)| zip(u, v, &f)).collect()))
+        },
+        // a vector is applied to every row
+        (Value::Matrix(m), Value::Vector(v)) if m[0].len() == v.len() => Some(Value::Matrix(m.iter().map(|u| zip(u, &v, &f)).collect())),
+        (Value::Vector(v), Value::Matrix(m)) if m[0].len() == v.len() => Some(Value::Matrix(m.iter().map(|u| zip(&v, u, &f)).collect())),
+        _ => None,
+    }
+}
+fn dot(a: Value, b: Value) -> Option<Value> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => Some(Value::Number(x * y)),
+        (Value::Vector(u), Value::Vector(v)) if u.len() == v.len() => Some(Value::Number(zip(&u, &v, |x, y| x * y).iter().sum())),
+        _ => None,
+    }
+}
+/// The matrix product, a vector on the left is a row and on the right a column. Numbers scale the other side.
+fn mat_mul(a: Value, b: Value) -> Option<Value> {
+
+    let column = |m: &[Vec<f32>], j: usize| m.iter().map(|row| row[j]).collect::<Vec<f32>>();
+    let dot = |u: &[f32], v: &[f32]| zip(u, v, |x, y| x * y).iter().sum::<f32>();
+
+    match (a, b) {
+        (Value::Number(x), b) => Some(b.map(|y| x * y)),
+        (a, Value::Number(y)) => Some(a.map(|x| x * y)),
+        (Value::Vector(u), Value::Vector(v)) if u.len() == v.len() => Some(Value::Number(dot(&u, &v))),
+        (Value::Matrix(m), Value::Vector(v)) if m[0].len() == v.len() => Some(Value::Vector(m.iter().map(|row| dot(row, &v)).collect())),
+        (Value::Vector(v), Value::Matrix(m)) if m.len() == v.len() => Some(Value::Vector((0..m[0].len()).map(|j| dot(&v, &column(&m, j))).collect())),
+        (Value::Matrix(m), Value::Matrix(n)) if m[0].len() == n.len() => {
+            let columns: Vec<Vec<f32>> = (0..n[0].len()).map(|j| column(&n, j)).collect();
+            Some(Value::Matrix(m.iter().map(|row| columns.iter().map(|c| dot(row, c)).collect()).collect()))
+        },
+        _ => None,
+    }
+}
+/// Swaps rows and columns, a vector becomes a matrix of one column.
+fn transpose(a: Value) -> Value {
+    match a {
+        Value::Number(n) => Value::Number(n),
+        Value::Vector(v) => Value::Matrix(v.into_iter().map(|x| vec![x]).collect()),
+        Value::Matrix(m) => Value::Matrix((0..m[0].len()).map(|j| m.iter().map(|row| row[j]).collect()).collect()),
+    }
+}
+/// The determinant by Gaussian elimination with partial pivoting, in double precision.
+fn det(m: Vec<Vec<f32>>) -> f32 {
+
+    let mut m: Vec<Vec<f64>> = m.into_iter().map(|row| row.into_iter().map(f64::from).collect()).collect();
+    let n = m.len();
+    let mut res = 1.0;
+
+    for i in 0..n {
+        let pivot = (i..n).max_by(|a, b| m[*a][i].abs().total_cmp(&m[*b][i].abs())).unwrap();
+
+        if m[pivot][i] == 0.0 {
+            return 0.0;
+        }
+        if pivot != i {
+            m.swap(pivot, i);
+            res = -res;
+        }
+        res *= m[i][i];
+
+        let (top, rest) = m.split_at_mut(i + 1);
+        for row in rest {
+            let factor = row[i] / top[i][i];
+            for (x, p) in row[i..].iter_mut().zip(&top[i][i..]) {
+                *x -= factor * p;
+            }
+        }
+    }
+    res as f32
+}
+
+#[test]
+fn vectors_and_matrices() {
+    use crate::{ast::parse_ast, eval_exp, format::{format_expr, FormatOptions}, latex::format_latex};
+
+    let mut scope = Scope::new();
+    scope.set("x", 3.0);
+
+    let eval = |exp: &str| eval_matrix(exp, &scope).unwrap_or_else(|err| panic!("{}: {}", exp, err));
+    let matrix = |rows: &[&[f32]]| Value::Matrix(rows.iter().map(|r| r.to_vec()).collect());
+
+    for (exp, value) in [
+        ("[1, 2, 3] * 2", Value::Vector(vec![2.0, 4.0, 6.0])),
+        ("dot([1, 2, 3], [4, 5, 6])", Value::Number(32.0)),
+        ("[[1,2],[3,4]] @ [5,6]", Value::Vector(vec![17.0, 39.0])),
+        ("[1, 2] @ [[1, 2], [3, 4]]", Value::Vector(vec![7.0, 10.0])),
+        ("transpose([[1, 2, 3], [4, 5, 6]])", matrix(&[&[1.0, 4.0], &[2.0, 5.0], &[3.0, 6.0]])),
+        ("transpose([1, 2]) @ [[3, 4]]", matrix(&[&[3.0, 4.0], &[6.0, 8.0]])),
+        ("[[1, 2], [3, 4]] @ [[0, 1], [1, 0]] / 2", matrix(&[&[1.0, 0.5], &[2.0, 1.5]])),
+        ("det([[1, 2], [3, 4]])", Value::Number(-2.0)),
+        ("det([[2, 0, 1], [1, 3, 2], [1, 1, 2]]) + det(5)", Value::Number(11.0)),
+        ("[[1, 2], [3, 4]] + [10, 20]", matrix(&[&[11.0, 22.0], &[13.0, 24.0]])),
+        ("-[1, -2]^2 + [2x, x]", Value::Vector(vec![5.0, -1.0])),
+        ("sqrt([4, 9]) + max([1, 5], 3)", Value::Vector(vec![5.0, 8.0])),
+        ("2 @ 3 - 1", Value::Number(5.0)),
+    ] {
+        assert_eq!(eval(exp), value, "{}", exp);
+    }
+
+    for (exp, err) in [
+        ("[1, 2] + [1, 2, 3]", "Shapes [2] and [3] don't match for \"+\" at 0..18"),
+        ("1 + [1, 2] @ [[1, 2]]", "Shapes [2] and [1x2] don't match for \"@\" at 4..21"),
+        ("dot([1, 2], [[1]])", "Shapes [2] and [1x1] don't match for \"dot\" at 0..18"),
+        ("[[1, 2], [3]]", "The rows of a matrix need the same length, not 2 and 1 at 0..13"),
+        ("[1, [2]]", "Expected a row of a matrix, not a number at 0..8"),
+        ("det([1, 2])", "det needs a square matrix, not [2] at 0..11"),
+        ("[1, 2)", "Expected \"]\" before \")\""),
+        ("[]", "Unexpected closing bracket immediately after opening"),
+    ] {
+        assert_eq!(eval_matrix(exp, &scope), Err(err.to_string()), "{}", exp);
+    }
+
+    // lists, the matrix functions and @ are only read here
+    let error = Err("Lists need the matrix evaluator".to_string());
+    for exp in ["[1, 2] * 2", "2 @ 3", "dot(2, 3)", "det(4)", "transpose(5)"] {
+        assert_eq!(eval_exp(exp), error, "{}", exp);
+        assert_eq!(crate::dates::eval_dates(exp, &scope).map(|_| 0.0), error, "{}", exp);
+    }
+    assert_eq!(parse_ast("[1]").err().unwrap(), "Lists need the matrix evaluator");
+    assert_eq!(crate::units::eval_units("dot(2 m, 3 m)", &scope).map(|m| m.value), error);
+    assert_eq!(crate::ast::evaluate_ast(&parse_matrix_ast("2 @ det(3)").unwrap(), &scope), error);
+
+    // variables can hold lists too
+    let values = HashMap::from([("m".to_string(), matrix(&[&[1.0, 2.0], &[3.0, 4.0]])), ("v".to_string(), Value::Vector(vec![1.0, 2.0]))]);
+    assert_eq!(eval_matrix_with("det(transpose(m)) + m @ v * x", &scope, &values), Ok(Value::Vector(vec![13.0, 31.0])));
+    assert_eq!(eval_matrix("transpose(m)", &scope), Err("Unknown variable: \"m\"".to_string()));
+
+    let m = eval("[[1, 2.5], [3, 4]]");
+    assert_eq!(m.to_string(), "[[1, 2.5], [3, 4]]");
+    assert_eq!(eval(&m.to_string()), m);
+
+    let exp = "[[1, 2], [3, x]] @ [2, -1] + dot([x, 1], [1, 2])";
+    assert_eq!(format_expr(&parse_matrix_ast(exp).unwrap(), &FormatOptions::default()), exp);
+    assert_eq!(format_latex(&parse_matrix_ast("[[1, 2], [3, 4]]").unwrap(), &FormatOptions::default()), "\\begin{bmatrix}1 & 2 \\\\ 3 & 4\\end{bmatrix}");
+}
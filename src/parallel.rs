@@ -0,0 +1,147 @@
+use std::{ops::Range, thread};
+
+use crate::{bytecode::{compile, evaluate_bytecode, Vm}, token_parse::{apply_operator, is_comparison, Operator, Token}};
+
+pub struct ParallelOptions {
+    /// Number of threads, 0 uses one for every core.
+    pub threads: usize,
+    /// Adds up the terms in order so the result is the same as the serial evaluators, bit for bit.
+    /// Otherwise every thread adds up its own terms and the sums are added at the end.
+    pub deterministic: bool,
+}
+impl Default for ParallelOptions {
+    fn default() -> Self {
+        ParallelOptions { threads: 0, deterministic: true }
+    }
+}
+/// Evaluates the terms of the top level `+`/`-` chain on multiple threads. The tokens are
+/// validated with implicit tokens added and variables resolved, like for the other evaluators.
+pub fn parallel_eval(tokens: &[Token], options: &ParallelOptions) -> f32 {
+
+    let threads = match options.threads {
+        0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        n => n,
+    };
+
+    if threads == 1 || tokens.len() < 10_000 {
+        return evaluate_bytecode(tokens);
+    }
+
+    let Some(terms) = split_terms(tokens) else {
+        return evaluate_bytecode(tokens);
+    };
+    let chunks = split_chunks(&terms, threads);
+
+    if options.deterministic {
+        let mut values = vec![0.0; terms.len()];
+
+        thread::scope(|s| {
+            let mut rest = values.as_mut_slice();
+
+            for chunk in chunks {
+                let (values, r) = rest.split_at_mut(chunk.len());
+                rest = r;
+                let terms = &terms[chunk];
+
+                s.spawn(move || {
+                    let mut vm = Vm::new();
+                    for (value, (_, term)) in values.iter_mut().zip(terms) {
+                        *value = vm.run(&compile(&tokens[term.clone()], &[]), &[]);
+                    }
+                });
+            }
+        });
+
+        let mut res = values[0];
+
+        for ((o, _), value) in terms.iter().zip(values).skip(1) {
+            res = apply_operator(o, res, value);
+        }
+        res
+    }
+    else {
+        thread::scope(|s| {
+            let sums: Vec<_> = chunks.into_iter().map(|chunk| {
+                let terms = &terms[chunk];
+
+                s.spawn(move || {
+                    let mut vm = Vm::new();
+                    terms.iter().fold(0.0, |acc, (o, term)| apply_operator(o, acc, vm.run(&compile(&tokens[term.clone()], &[]), &[])))
+                })
+            })
+            .collect();
+
+            sums.into_iter().map(|sum| sum.join().unwrap()).sum()
+        })
+    }
+}
+/// Splits the tokens at every `+` and `-` outside of brackets, the first term gets a `+`.
+/// `None` if there is a comparison outside of brackets.
+fn split_terms(tokens: &[Token]) -> Option<Vec<(Operator, Range<usize>)>> {
+
+    let mut terms = Vec::new();
+    let mut brackets = 0;
+    let mut start = 0;
+    let mut operator = Operator::Plus;
+
+    for (i, t) in tokens.iter().enumerate() {
+        match t {
+            Token::Bracket(b) => {
+                if *b { brackets += 1; }
+                else { brackets -= 1; }
+            },
+            Token::Operator(o @ (Operator::Plus | Operator::Minus)) if brackets == 0 => {
+                terms.push((operator, start..i));
+                operator = *o;
+                start = i + 1;
+            },
+            // the sums on both sides of a comparison aren't one chain
+            Token::Operator(o) if brackets == 0 && is_comparison(o) => return None,
+            _ => {},
+        }
+    }
+    terms.push((operator, start..tokens.len()));
+
+    Some(terms)
+}
+/// Splits the terms into ranges with about the same number of tokens.
+fn split_chunks(terms: &[(Operator, Range<usize>)], count: usize) -> Vec<Range<usize>> {
+
+    let size = terms.last().unwrap().1.end / count + 1;
+
+    let mut chunks = Vec::with_capacity(count);
+    let mut start = 0;
+
+    for (i, (_, term)) in terms.iter().enumerate() {
+        if term.end - terms[start].1.start >= size {
+            chunks.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    if start < terms.len() {
+        chunks.push(start..terms.len());
+    }
+    chunks
+}
+
+#[test]
+fn parallel_evaluation() {
+    use crate::prepare_tokens;
+
+    let mut exp = String::from("-1");
+    for i in 0..5000 {
+        exp += &format!("{}{}.{} * (2 - {}/7)^2", if i % 3 == 0 { '-' } else { '+' }, i % 97, i % 10, i % 13);
+    }
+    let tokens = prepare_tokens(&exp).unwrap();
+
+    let serial = evaluate_bytecode(&tokens);
+
+    let options = ParallelOptions { threads: 4, deterministic: true };
+    assert_eq!(parallel_eval(&tokens, &options).to_bits(), serial.to_bits());
+
+    let options = ParallelOptions { threads: 3, deterministic: false };
+    let res = parallel_eval(&tokens, &options);
+    assert!((res - serial).abs() <= serial.abs() * 1e-4, "{} {}", res, serial);
+
+    assert_eq!(parallel_eval(&prepare_tokens("1 + 2 * 3").unwrap(), &ParallelOptions::default()), 7.0);
+}
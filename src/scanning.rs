@@ -0,0 +1,205 @@
+use exp_parser::{functions::{call_function, Function, MAX_ARGUMENTS}, token_parse::{apply_operator, Operator, Token}};
+
+/// The evaluator before `linear_eval` kept its tokens in a linked list, compared against it in `measure`.
+/// Evaluated tokens are left behind as empty slots and every operator scans for its nearest
+/// remaining neighbours, so deeply nested brackets take quadratic time.
+pub fn linear_eval_scanning(tokens: Vec<Token>) -> f32 {
+    let tokens = tokens.into_iter().map(Some).collect();
+    let mut parser = SlotParser::new(tokens);
+
+    scanning_recursive(&mut parser, &mut 0, &mut 0)
+}
+fn scanning_recursive(tokens: &mut SlotParser, count: &mut usize, max: &mut usize) -> f32 {
+
+    let mut adds = Vec::new();
+    let mut muls = Vec::new();
+    let mut pows = Vec::new();
+
+    let mut start = tokens.index.wrapping_add(1);
+
+    while tokens.has_next() {
+        if let Some(s) = tokens.consume() {
+            match s {
+                Token::Number(_) => {},
+                Token::Function(_) => {},
+                Token::Variable(_) => unreachable!("variables are resolved before evaluation"),
+                Token::List(_) => unreachable!("lists are validated"),
+                Token::Str(_) => unreachable!("strings are validated"),
+                Token::Comma => {
+                    // every argument is evaluated on its own
+                    tokens.evaluate_segment(start, &adds, &muls, &pows);
+
+                    *count += muls.len() + adds.len() + pows.len();
+                    adds.clear();
+                    muls.clear();
+                    pows.clear();
+
+                    tokens.tokens[tokens.index] = None;
+                    start = tokens.index + 1;
+                },
+                Token::Operator(operator) => {
+                    match operator {
+                        Operator::Plus => adds.push((tokens.index, operator)),
+                        Operator::Minus => adds.push((tokens.index, operator)),
+                        Operator::Mul | Operator::MatMul => muls.push((tokens.index, operator)),
+                        Operator::Div => muls.push((tokens.index, operator)),
+                        Operator::Pow => pows.push((tokens.index, operator)),
+                        _ => unreachable!("comparisons are validated"),
+                    }
+                },
+                Token::Bracket(b) => {
+                    if b {
+                        let ind = tokens.index;
+                        scanning_recursive(tokens, count, max);
+                        tokens.tokens[ind] = None;
+
+                        if ind > 0 {
+                            if let Some(Token::Function(f)) = tokens.tokens[ind - 1] {
+                                tokens.call_function(ind - 1, f, tokens.index);
+                            }
+                        }
+                        tokens.index = ind;
+                    } 
+                    else {
+                        tokens.tokens[tokens.index] = None;
+                        break;
+                    }
+                },
+            }
+        }
+    }
+
+    let last = tokens.evaluate_segment(start, &adds, &muls, &pows);
+
+    let n = if let Some(Token::Number(n)) = tokens.tokens[last] {
+        n
+    }
+    else {
+        unreachable!();
+    };
+
+    *count += muls.len() + adds.len() + pows.len();
+
+    if *count > 10_000 && max.saturating_sub(tokens.index) > 10_000  {
+        filter_vec(&mut tokens.tokens, tokens.index + 1, *max);
+        *count = 0;
+        *max = 0;
+    }
+
+    *max = tokens.index.max(*max);
+
+    n
+}
+struct SlotParser {
+    tokens: Vec<Option<Token>>,
+    index: usize,
+}
+impl SlotParser {
+    fn new(tokens: Vec<Option<Token>>) -> Self {
+        SlotParser { tokens, index: usize::MAX }
+    }
+    fn consume(&mut self) -> Option<Token> {
+        self.index = self.index.wrapping_add(1);
+        self.tokens[self.index].clone()
+    }
+    fn has_next(&self) -> bool {
+        self.tokens.len() > self.index.wrapping_add(1)
+    }
+    /// Evaluates the operators of a bracket or function argument starting at `start` and returns the index of the result.
+    fn evaluate_segment(&mut self, start: usize, adds: &[(usize, Operator)], muls: &[(usize, Operator)], pows: &[(usize, Operator)]) -> usize {
+
+        // exponentiation is right associative
+        for pow in pows.iter().rev() {
+            self.evaluate_operator(pow.0, pow.1);
+        }
+
+        for mul in muls.iter() {
+            self.evaluate_operator(mul.0, mul.1);
+        }
+
+        for add in adds.iter() {
+            self.evaluate_operator(add.0, add.1);
+        }
+
+        if let Some(n) = adds.last()      { n.0 }
+        else if let Some(n) = muls.last() { n.0 }
+        else if let Some(n) = pows.first() { n.0 }
+        else { 
+            // a lone value, possibly left behind by a nested bracket
+            start + self.tokens[start..].iter().position(|x| x.is_some()).unwrap()
+        }
+    }
+    /// Replaces the function at `index` with its result, the arguments are the values left between it and `end`.
+    fn call_function(&mut self, index: usize, f: Function, end: usize) {
+
+        let mut args = [0.0; MAX_ARGUMENTS];
+        let mut count = 0;
+
+        for slot in self.tokens[index + 1..end].iter_mut() {
+            if let Some(Token::Number(n)) = slot.take() {
+                args[count] = n;
+                count += 1;
+            }
+        }
+
+        self.tokens[index] = Some(Token::Number(call_function(&f, &args[..count])));
+    }
+    fn evaluate_operator(&mut self, index: usize, o: Operator) {
+
+        let (i1, left_opt) = self.tokens[..index].iter()
+            .rev()
+            .enumerate()
+            .find_map(|x| x.1.as_ref().map(|a| (x.0, a)))
+            .unwrap();
+
+        let (i2, right_opt) = self.tokens[index + 1..].iter()
+            .enumerate()
+            .find_map(|x| x.1.as_ref().map(|a| (x.0, a)))
+            .unwrap();
+
+        let (left, right) = match (left_opt, right_opt) {
+            (Token::Number(n1), Token::Number(n2)) => (*n1, *n2),
+            _ => panic!("should always be numbers"),
+        };
+
+        self.tokens[index] = Some(Token::Number(apply_operator(&o, left, right)));
+
+        self.tokens[index - i1 - 1] = None;
+        self.tokens[index + i2 + 1] = None;
+    }
+}
+fn filter_vec(vec: &mut Vec<Option<Token>>, start: usize, end: usize) {
+    let mut a = start;
+    let mut b = start;
+
+    while a < end {
+        if vec[a].is_some() {
+            vec.swap(a, b);
+            b += 1;
+        }
+        a += 1;
+    }
+
+    vec.drain(b..end);
+}
+#[test]
+fn testtt() {
+    let mut vec = vec![ 
+        None, None, None, Some(Token::Number(3f32)), None, 
+        None, Some(Token::Number(3f32)), Some(Token::Number(3f32)), Some(Token::Number(3f32)), Some(Token::Number(3f32)), 
+        None, None, None ];
+
+    filter_vec(&mut vec, 5, 11);
+
+    let slots: Vec<bool> = vec.iter().map(|x| x.is_some()).collect();
+
+    assert_eq!(slots, [false, false, false, true, false, true, true, true, true, false, false]);
+}
+#[test]
+fn scanning_evaluation() {
+    use exp_parser::prepare_tokens;
+
+    for (exp, expected) in [("((((1 + 2)) * 3))", 9.0), ("2max(1 - 2, -(3)) + 1", -1.0)] {
+        assert_eq!(linear_eval_scanning(prepare_tokens(exp).unwrap()), expected, "{}", exp);
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use crate::{eval_exp_with_format, locale::NumberFormat, token_parse::Token};
+
+/// Variable bindings visible to an expression. A new scope starts out with the built-in constants.
+pub struct Scope {
+    vars: HashMap<String, f32>,
+}
+impl Scope {
+    pub fn new() -> Self {
+        let mut vars = HashMap::new();
+
+        vars.insert("pi".to_string(), std::f32::consts::PI);
+        vars.insert("e".to_string(), std::f32::consts::E);
+
+        Scope { vars }
+    }
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.vars.get(name).copied()
+    }
+    pub fn set(&mut self, name: &str, value: f32) {
+        self.vars.insert(name.to_string(), value);
+    }
+}
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+pub fn resolve_variables(tokens: &mut [Token], scope: &Scope) -> Result<(), String> {
+
+    for t in tokens.iter_mut() {
+        if let Token::Variable(name) = t {
+            match scope.get(name) {
+                Some(n) => *t = Token::Number(n),
+                None => return Err(format!("Unknown variable: \"{}\"", name)),
+            }
+        }
+    }
+    Ok(())
+}
+/// Evaluates `;` separated statements in a fresh scope and returns the value of the last one.
+/// 
+/// `let name = exp` binds the value of `exp` for all following statements.
+pub fn eval_script(script: &str) -> Result<f32, String> {
+    eval_script_in(script, &mut Scope::new())
+}
+pub fn eval_script_in(script: &str, scope: &mut Scope) -> Result<f32, String> {
+    eval_script_with_format(script, scope, &NumberFormat::default())
+}
+/// Like `eval_script_in`, numbers and argument lists are written as set in `format`.
+/// A `;` in brackets separates arguments, not statements.
+pub fn eval_script_with_format(script: &str, scope: &mut Scope, format: &NumberFormat) -> Result<f32, String> {
+
+    let mut result = None;
+
+    for (i, statement) in statements(script).enumerate() {
+        if statement.trim().is_empty() {
+            continue;
+        }
+
+        match eval_statement(statement.trim(), scope, format) {
+            Ok(n) => result = Some(n),
+            Err(err) => return Err(format!("Statement {}: {}", i + 1, err)),
+        }
+    }
+
+    result.ok_or_else(|| "Empty script".to_string())
+}
+/// Splits the script at every `;` outside of brackets.
+fn statements(script: &str) -> impl Iterator<Item = &str> {
+
+    let mut depth = 0usize;
+
+    script.split(move |c| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            _ => {},
+        }
+        c == ';' && depth == 0
+    })
+}
+fn eval_statement(statement: &str, scope: &mut Scope, format: &NumberFormat) -> Result<f32, String> {
+
+    let binding = match statement.strip_prefix("let") {
+        Some(rest) if rest.starts_with(char::is_whitespace) => rest,
+        _ => return eval_exp_with_format(statement, scope, format),
+    };
+
+    let (name, exp) = match binding.split_once('=') {
+        Some(s) => s,
+        None => return Err("Expected \"=\" after variable name".to_string()),
+    };
+    let name = name.trim();
+
+    if !is_identifier(name) || name == "let" {
+        return Err(format!("Invalid variable name: \"{}\"", name));
+    }
+
+    let n = eval_exp_with_format(exp, scope, format)?;
+    scope.set(name, n);
+
+    Ok(n)
+}
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+#[test]
+fn scripts() {
+    assert_eq!(eval_script("let r = 2; let area = pi * r^2; area * 3").unwrap(), std::f32::consts::PI * 4.0 * 3.0);
+    assert_eq!(eval_script("let x = 3; let x = x * 2; x + 1;").unwrap(), 7.0);
+    assert_eq!(eval_script("let a = 2").unwrap(), 2.0);
+
+    assert_eq!(eval_script("let a = 2; a * b").unwrap_err(), "Statement 2: Unknown variable: \"b\"");
+    assert_eq!(eval_script("1; let 2a = 1").unwrap_err(), "Statement 2: Invalid variable name: \"2a\"");
+    assert!(eval_script(" ; ").is_err());
+}
@@ -0,0 +1,141 @@
+use crate::{ast::{check_call, Expr, ExprKind, UnaryOp}, functions::{call_function, MAX_ARGUMENTS}, token_parse::{apply_operator, Operator, Span}, validation::Validator};
+
+/// Folds constant subtrees, removes operations that leave a value unchanged (`x*1`, `x + 0`, `x^1`, ...)
+/// and turns subtractions from zero into negations. Variables are kept, even the built-in constants.
+/// 
+/// Values are assumed to be finite, so `0*x` is simplified to `0`. Constants that fold to infinity
+/// or NaN are kept as they are, `1/0` is printed as `1/0` and not as `inf`, which reads back as a variable.
+pub fn simplify(expr: &Expr) -> Expr {
+    expr.fold(|e, mut children| {
+        let span = e.span.clone();
+
+        match &e.kind {
+            ExprKind::Num(_) | ExprKind::Var(_) | ExprKind::Str(_) => e.with_children(children),
+            ExprKind::Unary(UnaryOp::Neg, _) => negate(children.pop().unwrap(), span),
+            ExprKind::Binary(o, ..) => {
+                let right = children.pop().unwrap();
+                let left = children.pop().unwrap();
+                binary(*o, left, right, span)
+            },
+            ExprKind::Call(f, _) => {
+                let args = children;
+
+                // the parts of a complex number, the matrix and string functions are only known to their evaluators
+                if check_call(f, &args, &Validator::new()).is_err() {
+                    return Expr::new(ExprKind::Call(*f, args), span);
+                }
+                let mut values = [0.0; MAX_ARGUMENTS];
+                for (value, arg) in values.iter_mut().zip(args.iter()) {
+                    match arg.kind {
+                        ExprKind::Num(n) => *value = n,
+                        _ => return Expr::new(ExprKind::Call(*f, args), span),
+                    }
+                }
+
+                let n = call_function(f, &values[..args.len()]);
+
+                if !n.is_finite() {
+                    return Expr::new(ExprKind::Call(*f, args), span);
+                }
+                Expr::new(ExprKind::Num(n), span)
+            },
+            ExprKind::List(_) => Expr::new(ExprKind::List(children), span),
+        }
+    })
+}
+fn negate(e: Expr, span: Span) -> Expr {
+    let inner = e.span.clone();
+
+    match e.into_kind() {
+        ExprKind::Num(n) => Expr::new(ExprKind::Num(-n), span),
+        ExprKind::Unary(UnaryOp::Neg, e) => *e,
+        kind => Expr::new(ExprKind::Unary(UnaryOp::Neg, Box::new(Expr::new(kind, inner))), span),
+    }
+}
+fn binary(o: Operator, left: Expr, right: Expr, span: Span) -> Expr {
+
+    if let (ExprKind::Num(l), ExprKind::Num(r)) = (&left.kind, &right.kind) {
+        let n = apply_operator(&o, *l, *r);
+
+        if n.is_finite() {
+            return Expr::new(ExprKind::Num(n), span);
+        }
+        return Expr::new(ExprKind::Binary(o, Box::new(left), Box::new(right)), span);
+    }
+
+    match o {
+        Operator::Mul if is_number(&left, 0.0) || is_number(&right, 0.0) => Expr::new(ExprKind::Num(0.0), span),
+        Operator::Div if is_number(&left, 0.0) => Expr::new(ExprKind::Num(0.0), span),
+        Operator::Pow if is_number(&right, 0.0) => Expr::new(ExprKind::Num(1.0), span),
+        // a negation is moved out of a product, where it can cancel out
+        Operator::Mul | Operator::Div if is_negation(&left) => {
+            let left_span = left.span.clone();
+            negate(binary(o, negate(left, left_span), right, span.clone()), span)
+        },
+        Operator::Mul | Operator::Div if is_negation(&right) => {
+            let right_span = right.span.clone();
+            negate(binary(o, left, negate(right, right_span), span.clone()), span)
+        },
+        Operator::Plus if is_number(&left, 0.0) => right,
+        Operator::Plus | Operator::Minus if is_number(&right, 0.0) => left,
+        Operator::Minus if is_number(&left, 0.0) => negate(right, span),
+        Operator::Mul if is_number(&left, 1.0) => right,
+        Operator::Mul | Operator::Div | Operator::Pow if is_number(&right, 1.0) => left,
+        // adding a negative value is a subtraction and the other way around
+        Operator::Plus | Operator::Minus if is_negation(&right) => {
+            let o = if o == Operator::Plus { Operator::Minus } else { Operator::Plus };
+            let right_span = right.span.clone();
+
+            Expr::new(ExprKind::Binary(o, Box::new(left), Box::new(negate(right, right_span))), span)
+        },
+        _ => Expr::new(ExprKind::Binary(o, Box::new(left), Box::new(right)), span),
+    }
+}
+fn is_number(e: &Expr, n: f32) -> bool {
+    matches!(e.kind, ExprKind::Num(m) if m == n)
+}
+fn is_negation(e: &Expr) -> bool {
+    match e.kind {
+        ExprKind::Num(n) => n < 0.0,
+        ExprKind::Unary(UnaryOp::Neg, _) => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn simplification() {
+    use crate::{ast::{evaluate_ast, parse_ast}, script::Scope};
+
+    for (exp, simplified) in [
+        ("2*3+x", "6 + x"),
+        ("x*1 + 0 - y^1 / 1", "x - y"),
+        ("1x + 0(2)", "x"),
+        ("-x^2", "-x^2"),
+        ("-(x + 1)", "-(x + 1)"),
+        ("2(-x)", "-2*x"),
+        ("0 x + y^0 + (-x)/(-y)", "1 + x/y"),
+        ("y + (-3)", "y - 3"),
+        ("x - (0 - y)", "x + y"),
+        ("-(-(x))", "x"),
+        ("(-2)^2 + x", "4 + x"),
+        ("(x - 1)^y^(1/2)", "(x - 1)^y^0.5"),
+        ("(x^2)^3 - (x - y) - (x / y) / 2", "(x^2)^3 - (x - y) - x/y/2"),
+        ("max(1 + 1, 3) x + min(x, 2*2)", "3*x + min(x, 4)"),
+        ("x + 2/(1 - 1)", "x + 2/0"),
+        ("-ln(0) * 2", "-ln(0)*2"),
+    ] {
+        let expr = parse_ast(exp).unwrap();
+        let s = simplify(&expr);
+
+        assert_eq!(s.to_string(), simplified, "{}", exp);
+
+        let mut scope = Scope::new();
+        scope.set("x", 3.0);
+        scope.set("y", 5.0);
+
+        assert_eq!(evaluate_ast(&s, &scope), evaluate_ast(&expr, &scope), "{}", exp);
+        assert_eq!(simplify(&parse_ast(simplified).unwrap()).to_string(), simplified, "{}", exp);
+    }
+
+    assert_eq!(simplify(&parse_ast("sqrt(-1) + 0^0").unwrap()).to_string(), "sqrt(-1) + 1");
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+
+use crate::{ast::{ast_from_string_tokens, check_call, Expr, ExprKind, UnaryOp}, format::is_implicit_zero, functions::{call_function, function_to_string, Function}, script::Scope, token_parse::{apply_operator, is_comparison, operator_to_string, parse_exp_spans, quote, Operator}, validation::{Validator, LISTS}};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextValue {
+    Number(f32),
+    Str(String),
+}
+impl TextValue {
+    fn kind(&self) -> &'static str {
+        match self {
+            TextValue::Number(_) => "a number",
+            TextValue::Str(_) => "a string",
+        }
+    }
+}
+/// Prints numbers like `Display` and strings in quotes as they are written, `"a\"b"`.
+impl fmt::Display for TextValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextValue::Number(n) => write!(f, "{}", n),
+            TextValue::Str(text) => f.write_str(&quote(text)),
+        }
+    }
+}
+/// Evaluates the input with strings like `"abc"`, escaped as in `"a\"b\n"`.
+///
+/// `+` joins two strings, `len`, `upper` and `substr(s, start, length)` count characters from 0,
+/// `str` and `num` convert between numbers and strings. Any other operator or function takes numbers.
+/// The comparisons `==`, `!=`, `<`, `<=`, `>` and `>=` bind weakest and take two numbers or two strings,
+/// the result is 1 if it holds and 0 otherwise. Strings are compared by their characters, `1 < 2 < 3` isn't allowed.
+pub fn eval_strings(input: &str, scope: &Scope) -> Result<TextValue, String> {
+    evaluate_text(&parse_string_ast(input)?, scope)
+}
+/// Like `parse_ast`, strings, the string functions and comparisons are allowed.
+pub fn parse_string_ast(input: &str) -> Result<Expr, String> {
+    ast_from_string_tokens(parse_exp_spans(input)?)
+}
+/// Evaluates the tree, variables of the scope are numbers.
+pub fn evaluate_text(expr: &Expr, scope: &Scope) -> Result<TextValue, String> {
+    use TextValue::{Number, Str};
+
+    expr.try_fold(|expr, values: Vec<TextValue>| match &expr.kind {
+        ExprKind::Num(n) => Ok(Number(*n)),
+        ExprKind::Str(text) => Ok(Str(text.clone())),
+        ExprKind::Var(name) => scope.get(name).map(Number).ok_or_else(|| format!("Unknown variable: \"{}\"", name)),
+        ExprKind::Unary(UnaryOp::Neg, _) => negate(values.into_iter().next().unwrap()),
+        // a leading minus is parsed as a subtraction from zero
+        ExprKind::Binary(Operator::Minus, zero, _) if is_implicit_zero(zero) => negate(values.into_iter().nth(1).unwrap()),
+        ExprKind::Binary(Operator::MatMul, ..) => Err(LISTS.to_string()),
+        ExprKind::Binary(o, ..) => {
+            let mut values = values.into_iter();
+
+            match (values.next().unwrap(), values.next().unwrap()) {
+                (Number(x), Number(y)) => Ok(Number(apply_operator(o, x, y))),
+                (Str(a), Str(b)) if *o == Operator::Plus => Ok(Str(a + &b)),
+                // the ordering of two strings compares to zero like they compare to each other
+                (Str(a), Str(b)) if is_comparison(o) => Ok(Number(apply_operator(o, a.cmp(&b) as i32 as f32, 0.0))),
+                (a, b) => Err(format!("\"{}\" doesn't work on {} and {}", operator_to_string(o), a.kind(), b.kind())),
+            }
+        },
+        ExprKind::Call(f, args) => {
+            check_call(f, args, &Validator::with_strings())?;
+
+            match (f, &values[..]) {
+                (Function::Str, [Number(n)]) => return Ok(Str(n.to_string())),
+                (Function::Num, [Str(text)]) => {
+                    return match text.trim().parse::<f32>() {
+                        Ok(n) if n.is_finite() => Ok(Number(n)),
+                        _ => Err(format!("Not a number: {}", quote(text))),
+                    };
+                },
+                (Function::Str | Function::Num, [value]) => return Ok(value.clone()),
+                _ => {},
+            }
+
+            // the string functions take a string first, everything else is a number
+            let takes_string = matches!(f, Function::Len | Function::Upper | Function::Substr);
+            let expected = |i: usize| if i == 0 && takes_string { "a string" } else { "a number" };
+
+            if let Some((i, value)) = values.iter().enumerate().find(|(i, value)| value.kind() != expected(*i)) {
+                return Err(format!("{} needs {}, not {}", function_to_string(f), expected(i), value.kind()));
+            }
+
+            match (f, &values[..]) {
+                (Function::Len, [Str(text)]) => Ok(Number(text.chars().count() as f32)),
+                (Function::Upper, [Str(text)]) => Ok(Str(text.to_uppercase())),
+                (Function::Substr, [Str(text), Number(start), Number(length)]) => {
+                    let count = |n: f32| if n >= 0.0 && n.fract() == 0.0 { Ok(n as usize) } else { Err(format!("substr needs a whole number of characters, not {}", n)) };
+                    Ok(Str(text.chars().skip(count(*start)?).take(count(*length)?).collect()))
+                },
+                _ => {
+                    let numbers: Vec<f32> = values.iter().map(|v| match v { Number(n) => *n, Str(_) => unreachable!("the kinds are checked") }).collect();
+                    Ok(Number(call_function(f, &numbers)))
+                },
+            }
+        },
+        ExprKind::List(_) => Err(LISTS.to_string()),
+    })
+}
+fn negate(value: TextValue) -> Result<TextValue, String> {
+    match value {
+        TextValue::Number(n) => Ok(TextValue::Number(-n)),
+        TextValue::Str(_) => Err("A string can't be negated".to_string()),
+    }
+}
+
+#[test]
+fn strings_and_functions() {
+    use crate::{eval_exp, format::{format_expr, FormatOptions}};
+
+    let mut scope = Scope::new();
+    scope.set("x", 2.5);
+
+    let eval = |exp: &str| eval_strings(exp, &scope).unwrap_or_else(|err| panic!("{}: {}", exp, err));
+    let text = |s: &str| TextValue::Str(s.to_string());
+
+    for (exp, value) in [
+        ("\"abc\"", text("abc")),
+        ("\"a\\\"b\\\\c\\n\\t\"", text("a\"b\\c\n\t")),
+        ("\"Hello, \" + upper(\"wörld\") + \"!\"", text("Hello, WÖRLD!")),
+        ("len(\"grüße\") * 2", TextValue::Number(10.0)),
+        ("substr(\"templating\", 2 * 2, 4)", text("lati")),
+        ("substr(\"abc\", 1, 10) + substr(\"abc\", 5, 1)", text("bc")),
+        ("\"x = \" + str(x * 2)", text("x = 5")),
+        ("num(\" 1e3 \") + num(str(-x))", TextValue::Number(997.5)),
+        ("-len(\"ab\") + max(1, sqrt(16))", TextValue::Number(2.0)),
+        ("\"abc\" < \"abd\"", TextValue::Number(1.0)),
+        ("upper(\"a\") == \"A\"", TextValue::Number(1.0)),
+        ("\"a<b\" != \"a\" + \"<b\"", TextValue::Number(0.0)),
+        ("len(\"(\") >= x", TextValue::Number(0.0)),
+        ("2 > 1", TextValue::Number(1.0)),
+        ("(1 < 2) + (\"b\" >= \"a\")", TextValue::Number(2.0)),
+        ("max(x <= 2, 3 ≠ 3) == 0", TextValue::Number(1.0)),
+        ("1 < -2", TextValue::Number(0.0)),
+        ("-x >= -3 * x", TextValue::Number(1.0)),
+    ] {
+        assert_eq!(eval(exp), value, "{}", exp);
+    }
+
+    for (exp, err) in [
+        ("\"a\" * 2", "\"*\" doesn't work on a string and a number"),
+        ("\"n = \" + 1", "\"+\" doesn't work on a string and a number"),
+        ("-\"a\"", "A string can't be negated"),
+        ("sqrt(\"4\")", "sqrt needs a number, not a string"),
+        ("len(12)", "len needs a string, not a number"),
+        ("substr(\"abc\", \"1\", 1)", "substr needs a number, not a string"),
+        ("substr(\"abc\", -1, 1)", "substr needs a whole number of characters, not -1"),
+        ("num(\"abc\")", "Not a number: \"abc\""),
+        ("\"a\" < 1", "\"<\" doesn't work on a string and a number"),
+        ("1 < 2 < 3", "Unexpected comparison \"<\" after another comparison"),
+        ("max(1 < 2 == 1, 0)", "Unexpected comparison \"==\" after another comparison"),
+        ("1 = 2", "Unexpected character: \"=\""),
+        ("!x", "Unexpected character: \"!\""),
+        ("1 < *2", "Unexpected operator after another operator"),
+        ("1 < --2", "Unexpected operator after another operator"),
+        ("1 <", "Unexpected operator at the end"),
+        ("\"abc", "Unclosed string"),
+        ("\"a\\q\"", "Unknown escape in string: \"\\q\""),
+        ("\"a\" \"b\"", "Expected an operator after string"),
+        ("x \"b\"", "Expected an operator before string"),
+        ("\"a\"(1)", "Expected an operator after string"),
+        ("== 1", "Unexpected operator at the start"),
+    ] {
+        assert_eq!(eval_strings(exp, &scope), Err(err.to_string()), "{}", exp);
+    }
+
+    // strings are only read here
+    assert_eq!(eval_exp("\"a\""), Err("Strings need the string evaluator".to_string()));
+    assert_eq!(eval_exp("len(2)"), Err("Strings need the string evaluator".to_string()));
+    assert_eq!(eval_exp("1 < 2"), Err("Comparisons need the string evaluator".to_string()));
+    let compared = parse_string_ast("x >= 1").unwrap();
+    assert_eq!(crate::ast::evaluate_ast(&compared, &scope), Err("Comparisons need the string evaluator".to_string()));
+    assert_eq!(crate::derivative::differentiate(&compared, "x").err().unwrap(), "Comparisons need the string evaluator");
+
+    let value = eval("\"say \\\"hi\\\"\\n\"");
+    assert_eq!(value.to_string(), "\"say \\\"hi\\\"\\n\"");
+    assert_eq!(eval(&value.to_string()), value);
+
+    // a string is never juxtaposed
+    let exp = "\"a\\\\\" + upper(substr(\"bcd\", 0, x))2 + 2*\"c\"";
+    assert_eq!(format_expr(&parse_string_ast(exp).unwrap(), &FormatOptions { implicit_mul: true }), exp);
+
+    let exp = "-(x > 1) + (1 < 2 + x) == 2*x";
+    assert_eq!(format_expr(&parse_string_ast(exp).unwrap(), &FormatOptions::default()), exp);
+
+    let exp = "x < -2*x";
+    assert_eq!(format_expr(&parse_string_ast(exp).unwrap(), &FormatOptions::default()), exp);
+}
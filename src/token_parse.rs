@@ -1,107 +1,718 @@
 use core::str;
+use std::{io::{ErrorKind, Read}, ops::Range};
 
-pub fn parse_exp(exp: &str) -> Result< Vec<Token>, String > {
+use crate::{functions::{function_from_name, function_to_string, is_extension_function, Function}, locale::NumberFormat};
 
-    // 40.88
-    //
-    //
+/// Byte range of a token in the input.
+pub type Span = Range<usize>;
 
-    let mut res = Vec::with_capacity(exp.len() / 2);
-    let mut parser = Parser::new(exp.to_string().into_bytes());
+pub fn parse_exp(exp: &str) -> Result< Vec<Token>, String > {
+    parse_exp_with_format(exp, &NumberFormat::default())
+}
+/// Like `parse_exp`, numbers and argument lists are written as set in `format`.
+pub fn parse_exp_with_format(exp: &str, format: &NumberFormat) -> Result< Vec<Token>, String > {
+    Tokens::with_format(exp, format).map(|t| t.map(|(t, _)| t)).collect()
+}
+/// Like `parse_exp`, also returns the span of every token.
+pub fn parse_exp_spans(exp: &str) -> Result< (Vec<Token>, Vec<Span>), String > {
+    Tokens::new(exp).collect::<Result<Vec<_>, _>>().map(|t| t.into_iter().unzip())
+}
+/// The tokens of a string, read one at a time without copying the input.
+/// Iteration stops after the first error.
+pub struct Tokens<'a> {
+    input: &'a [u8],
+    index: usize,
+    format: NumberFormat,
+    // the rest of the tokens a symbol like `²` stands for, in reverse order
+    pending: Vec<(Token, Span)>,
+}
+impl<'a> Tokens<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Tokens::with_format(input, &NumberFormat::default())
+    }
+    pub fn with_format(input: &'a str, format: &NumberFormat) -> Self {
+        Tokens { input: input.as_bytes(), index: 0, format: *format, pending: Vec::new() }
+    }
+}
+impl Iterator for Tokens<'_> {
+    type Item = Result<(Token, Span), String>;
 
-    while parser.has_next() {
-        match parser.consume() {
-            b' ' | b'_' => {},
+    fn next(&mut self) -> Option<Self::Item> {
 
-            b'(' => res.push(Token::Bracket(true)),
-            b')' => res.push(Token::Bracket(false)),
+        if let Some(t) = self.pending.pop() {
+            return Some(Ok(t));
+        }
 
-            b'+' => res.push(Token::Operator(Operator::Plus)),
-            b'-' => res.push(Token::Operator(Operator::Minus)),
-            b'*' => res.push(Token::Operator(Operator::Mul)),
-            b'/' => res.push(Token::Operator(Operator::Div)),
+        while self.index < self.input.len() {
+            let start = self.index;
 
-            _ => res.push(Token::Number(parse_number(&mut parser)?)),
+            match lex(&self.input[start..], false, &self.format) {
+                Ok(Lexed::Skip(len)) => self.index += len,
+                Ok(Lexed::Token(t, len)) => {
+                    self.index += len;
+                    return Some(Ok((t, start..self.index)));
+                },
+                Ok(Lexed::Tokens(tokens, len)) => {
+                    self.index += len;
+                    self.pending = tokens.into_iter().rev().map(|t| (t, start..self.index)).collect();
+                    return self.pending.pop().map(Ok);
+                },
+                Ok(Lexed::More) => unreachable!("the whole input is available"),
+                Err(err) => {
+                    self.index = self.input.len();
+                    return Some(Err(err));
+                },
+            }
         }
+        None
     }
-    return Ok(res);
 }
-fn parse_number(p: &mut Parser) -> Result<f32, String> {
-    
-    p.back();
-    let start = p.index;
-    let mut end = p.index;
+/// Bytes read from the reader at a time.
+const CHUNK: usize = 64 * 1024;
 
-    while p.has_next() {
-        let c = p.consume();
+/// The tokens of everything `reader` returns. The input is read in chunks, only the
+/// unread part of the current chunk is kept, so a token may not be longer than memory allows.
+/// Iteration stops after the first error.
+pub struct ReadTokens<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+    // position of the start of the buffer in the input
+    offset: usize,
+    eof: bool,
+    format: NumberFormat,
+    pending: Vec<(Token, Span)>,
+}
+impl<R: Read> ReadTokens<R> {
+    pub fn new(reader: R) -> Self {
+        ReadTokens::with_format(reader, &NumberFormat::default())
+    }
+    pub fn with_format(reader: R, format: &NumberFormat) -> Self {
+        ReadTokens { reader, buffer: vec![0; CHUNK], start: 0, end: 0, offset: 0, eof: false, format: *format, pending: Vec::new() }
+    }
+    fn stop(&mut self) {
+        self.start = self.end;
+        self.eof = true;
+    }
+}
+impl<R: Read> Iterator for ReadTokens<R> {
+    type Item = Result<(Token, Span), String>;
 
-        if (c as char).is_ascii_digit() || c == b'.' {
-            end += 1;
+    fn next(&mut self) -> Option<Self::Item> {
+
+        if let Some(t) = self.pending.pop() {
+            return Some(Ok(t));
         }
-        else {
-            p.back();
-            break;
+
+        loop {
+            if self.start < self.end {
+                match lex(&self.buffer[self.start..self.end], !self.eof, &self.format) {
+                    Ok(Lexed::Skip(len)) => {
+                        self.start += len;
+                        continue;
+                    },
+                    Ok(Lexed::Token(t, len)) => {
+                        let start = self.offset + self.start;
+                        self.start += len;
+                        return Some(Ok((t, start..start + len)));
+                    },
+                    Ok(Lexed::Tokens(tokens, len)) => {
+                        let start = self.offset + self.start;
+                        self.start += len;
+                        self.pending = tokens.into_iter().rev().map(|t| (t, start..start + len)).collect();
+                        return self.pending.pop().map(Ok);
+                    },
+                    // the token could continue in the next chunk
+                    Ok(Lexed::More) => {},
+                    Err(err) => {
+                        self.stop();
+                        return Some(Err(err));
+                    },
+                }
+            }
+            else if self.eof {
+                return None;
+            }
+
+            // keep the unread bytes and fill the rest of the buffer
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.offset += self.start;
+            self.end -= self.start;
+            self.start = 0;
+
+            if self.end == self.buffer.len() {
+                self.buffer.resize(self.buffer.len() * 2, 0);
+            }
+
+            match self.reader.read(&mut self.buffer[self.end..]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.end += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {},
+                Err(err) => {
+                    self.stop();
+                    return Some(Err(format!("Failed to read input: {}", err)));
+                },
+            }
         }
     }
+}
+enum Lexed {
+    // the token and its length in bytes
+    Token(Token, usize),
+    // the tokens a symbol stands for, which all get its span
+    Tokens(Vec<Token>, usize),
+    // whitespace of the length
+    Skip(usize),
+    More,
+}
+/// Reads the token at the start of `bytes`. With `more` set the input continues after `bytes`,
+/// so a number or name reaching the end asks for more input.
+fn lex(bytes: &[u8], more: bool, format: &NumberFormat) -> Result<Lexed, String> {
 
-    if start == end {
-        return  Err((p.consume() as char).to_string());
-    }
-    
-    let s = str::from_utf8(&p.buffer[start..end]).unwrap().trim();
+    let token = match bytes[0] {
+        c if c.is_ascii_whitespace() => return Ok(Lexed::Skip(1)),
+        c if !c.is_ascii() => return lex_symbol(bytes, more, format),
+
+        b'(' => Token::Bracket(true),
+        b')' => Token::Bracket(false),
+        b'[' => Token::List(true),
+        b']' => Token::List(false),
+        b'+' => Token::Operator(Operator::Plus),
+        b'-' => Token::Operator(Operator::Minus),
+        b'*' => Token::Operator(Operator::Mul),
+        b'/' => Token::Operator(Operator::Div),
+        b'^' => Token::Operator(Operator::Pow),
+        b'@' => Token::Operator(Operator::MatMul),
+        b'"' => return lex_string(bytes, more),
+        b'=' | b'!' | b'<' | b'>' => return lex_comparison(bytes, more),
+
+        c if c.is_ascii_alphabetic() => {
+            let len = run_length(bytes, |c| c.is_ascii_alphanumeric() || c == b'_');
+
+            if more && len == bytes.len() {
+                return Ok(Lexed::More);
+            }
+
+            let name = str::from_utf8(&bytes[..len]).unwrap();
+
+            let token = match function_from_name(name) {
+                Some(f) if is_extension_function(&f) => {
+                    let spaces = run_length(&bytes[len..], |c| c.is_ascii_whitespace());
+
+                    if more && len + spaces == bytes.len() {
+                        return Ok(Lexed::More);
+                    }
+                    if bytes.get(len + spaces) == Some(&b'(') { Token::Function(f) } else { Token::Variable(name.to_string()) }
+                },
+                Some(f) => Token::Function(f),
+                None => Token::Variable(name.to_string()),
+            };
+            return Ok(Lexed::Token(token, len));
+        },
+
+        c if c.is_ascii_digit() || is(c, format.decimal) => return lex_number(bytes, more, format),
+        c if is(c, format.argument) => Token::Comma,
 
-    match s.parse() {
-        Ok(f) => Ok(f),
-        Err(_) => Err(s.to_string()),
+        c => return Err(format!("Unexpected character: \"{}\"", c as char)),
+    };
+
+    Ok(Lexed::Token(token, 1))
+}
+/// Reads `==`, `!=`, `<`, `<=`, `>` or `>=`.
+fn lex_comparison(bytes: &[u8], more: bool) -> Result<Lexed, String> {
+
+    let equals = match bytes.get(1) {
+        None if more => return Ok(Lexed::More),
+        next => next == Some(&b'='),
+    };
+
+    let comparison = match (bytes[0], equals) {
+        (b'=', true) => Operator::Equal,
+        (b'!', true) => Operator::NotEqual,
+        (b'<', true) => Operator::LessEqual,
+        (b'>', true) => Operator::GreaterEqual,
+        (b'<', false) => Operator::Less,
+        (b'>', false) => Operator::Greater,
+        (c, _) => return Err(format!("Unexpected character: \"{}\"", c as char)),
+    };
+    Ok(Lexed::Token(Token::Operator(comparison), if equals { 2 } else { 1 }))
+}
+/// Reads a string in double quotes, with `\"`, `\\`, `\n`, `\t` and `\r` as escapes.
+fn lex_string(bytes: &[u8], more: bool) -> Result<Lexed, String> {
+
+    let unclosed = if more { Ok(Lexed::More) } else { Err("Unclosed string".to_string()) };
+
+    let mut text = Vec::new();
+    let mut i = 1;
+
+    loop {
+        match bytes.get(i) {
+            Some(b'"') => break,
+            Some(b'\\') => {
+                let c = match bytes.get(i + 1) {
+                    Some(b'"') => b'"',
+                    Some(b'\\') => b'\\',
+                    Some(b'n') => b'\n',
+                    Some(b't') => b'\t',
+                    Some(b'r') => b'\r',
+                    Some(_) => return match decode(&bytes[i + 1..])? {
+                        Some((c, _)) => Err(format!("Unknown escape in string: \"\\{}\"", c)),
+                        None if more => Ok(Lexed::More),
+                        None => Err("Invalid UTF-8 in input".to_string()),
+                    },
+                    None => return unclosed,
+                };
+                text.push(c);
+                i += 2;
+            },
+            Some(c) => {
+                text.push(*c);
+                i += 1;
+            },
+            None => return unclosed,
+        }
     }
+
+    let text = String::from_utf8(text).map_err(|_| "Invalid UTF-8 in input".to_string())?;
+
+    Ok(Lexed::Token(Token::Str(text), i + 1))
 }
-struct Parser {
-    index: usize,
-    buffer: Vec<u8>,
+/// Reads a character that isn't ASCII: Unicode whitespace, `×`, `·`, `÷`, `−`, `≠`, `≤`, `≥`, `π`, `√` and superscripts.
+/// `√` followed by a number or name reads as `sqrt(x)`, a superscript like `²` or `⁻¹` as a power.
+fn lex_symbol(bytes: &[u8], more: bool, format: &NumberFormat) -> Result<Lexed, String> {
+
+    let Some((c, len)) = decode(bytes)? else {
+        return if more { Ok(Lexed::More) } else { Err("Invalid UTF-8 in input".to_string()) };
+    };
+
+    let token = match c {
+        '×' | '·' | '⋅' | '∙' => Token::Operator(Operator::Mul),
+        '÷' | '∕' => Token::Operator(Operator::Div),
+        '−' => Token::Operator(Operator::Minus),
+        '≠' => Token::Operator(Operator::NotEqual),
+        '≤' => Token::Operator(Operator::LessEqual),
+        '≥' => Token::Operator(Operator::GreaterEqual),
+        'π' => Token::Variable("pi".to_string()),
+        '√' => {
+            let rest = &bytes[len..];
+
+            if rest.is_empty() {
+                return Ok(if more { Lexed::More } else { Lexed::Token(Token::Function(Function::Sqrt), len) });
+            }
+
+            return match lex(rest, more, format) {
+                Ok(Lexed::More) => Ok(Lexed::More),
+                Ok(Lexed::Token(t @ (Token::Number(_) | Token::Variable(_)), operand)) => {
+                    Ok(Lexed::Tokens(vec![Token::Function(Function::Sqrt), Token::Bracket(true), t, Token::Bracket(false)], len + operand))
+                },
+                // anything else is read on its own, `√(x)` is a call already
+                _ => Ok(Lexed::Token(Token::Function(Function::Sqrt), len)),
+            };
+        },
+        '⁻' | '⁰' | '¹' | '²' | '³' | '⁴'..='⁹' => {
+            let mut len = 0;
+            let mut digits = String::new();
+
+            loop {
+                match decode(&bytes[len..])? {
+                    Some(('⁻', w)) if len == 0 => len += w,
+                    Some((c, w)) if superscript_digit(c).is_some() => {
+                        digits.push(superscript_digit(c).unwrap());
+                        len += w;
+                    },
+                    None if more => return Ok(Lexed::More),
+                    _ => break,
+                }
+            }
+
+            let power = Token::Operator(Operator::Pow);
+            let n = match digits.parse() {
+                Ok(n) => Token::Number(n),
+                Err(_) => return Err("Expected a superscript digit after \"⁻\"".to_string()),
+            };
+
+            return Ok(match c {
+                '⁻' => Lexed::Tokens(vec![power, Token::Bracket(true), Token::Operator(Operator::Minus), n, Token::Bracket(false)], len),
+                _ => Lexed::Tokens(vec![power, n], len),
+            });
+        },
+        c if c.is_whitespace() => return Ok(Lexed::Skip(len)),
+        c => return Err(format!("Unexpected character: \"{}\"", c)),
+    };
+
+    Ok(Lexed::Token(token, len))
 }
-impl Parser {
-    pub fn new(buffer: Vec<u8>) -> Self {
-        Parser { buffer, index: 0 }
+/// The character at the start of `bytes` and its length, `None` if `bytes` ends before it does.
+fn decode(bytes: &[u8]) -> Result<Option<(char, usize)>, String> {
+
+    let len = match bytes.first() {
+        None => return Ok(None),
+        Some(0x00..=0x7F) => 1,
+        Some(0xC0..=0xDF) => 2,
+        Some(0xE0..=0xEF) => 3,
+        Some(0xF0..=0xF7) => 4,
+        Some(_) => return Err("Invalid UTF-8 in input".to_string()),
+    };
+
+    if bytes.len() < len {
+        return Ok(None);
     }
-    pub fn consume(&mut self) -> u8 {
-        self.index += 1;
-        self.buffer[self.index -1]
+
+    match str::from_utf8(&bytes[..len]) {
+        Ok(s) => Ok(s.chars().next().map(|c| (c, len))),
+        Err(_) => Err("Invalid UTF-8 in input".to_string()),
     }
-    pub fn has_next(&self) -> bool {
-        self.buffer.len() > self.index
+}
+fn superscript_digit(c: char) -> Option<char> {
+    match c {
+        '⁰' => Some('0'),
+        '¹' => Some('1'),
+        '²' => Some('2'),
+        '³' => Some('3'),
+        '⁴'..='⁹' => char::from_u32(c as u32 - '⁴' as u32 + '4' as u32),
+        _ => None,
     }
-    pub fn back(&mut self) {
-        self.index -= 1;
+}
+/// Reads a decimal number with an optional exponent as in `6.02e23`, or an integer with
+/// a `0x`, `0o` or `0b` prefix. Digits may be separated by `_` as in `1_000`.
+fn lex_number(bytes: &[u8], more: bool, format: &NumberFormat) -> Result<Lexed, String> {
+
+    let radix = match bytes {
+        [b'0', b'x' | b'X', ..] => Some((16, "hexadecimal")),
+        [b'0', b'o' | b'O', ..] => Some((8, "octal")),
+        [b'0', b'b' | b'B', ..] => Some((2, "binary")),
+        _ => None,
+    };
+
+    let len = match radix {
+        // letters are read as well, so `0xFG` is an error instead of a number followed by a name
+        Some(_) => 2 + run_length(&bytes[2..], |c| c.is_ascii_alphanumeric() || c == b'_'),
+        None => {
+            let mut len = 0;
+
+            while let Some(&c) = bytes.get(len) {
+                let digit_follows = bytes.get(len + 1).is_some_and(u8::is_ascii_digit);
+
+                // a thousands separator that isn't followed by a digit ends the number, as in `1, 2`
+                let separator = format.thousands.is_some_and(|t| is(c, t)) && (digit_follows || (more && len + 1 == bytes.len()));
+
+                if !(c.is_ascii_digit() || c == b'_' || is(c, format.decimal) || separator) {
+                    break;
+                }
+                len += 1;
+            }
+
+            if let Some(b'e' | b'E') = bytes.get(len) {
+                let sign = matches!(bytes.get(len + 1), Some(b'+' | b'-')) as usize;
+
+                match bytes.get(len + 1 + sign) {
+                    Some(c) if c.is_ascii_digit() => {
+                        len += 1 + sign;
+                        len += run_length(&bytes[len..], |c| c.is_ascii_digit() || c == b'_');
+                    },
+                    // a name starting with `e` after the number, like in `2exp(1)`
+                    Some(c) if sign == 0 && (c.is_ascii_alphabetic() || *c == b'_') => {},
+                    None if more => return Ok(Lexed::More),
+                    _ => {
+                        let s = str::from_utf8(&bytes[..len + 1 + sign]).unwrap();
+                        return Err(format!("Invalid number format: \"{}\", the exponent has no digits", s));
+                    },
+                }
+            }
+            len
+        },
+    };
+
+    if more && len == bytes.len() {
+        return Ok(Lexed::More);
+    }
+
+    let s = str::from_utf8(&bytes[..len]).unwrap();
+
+    let n = match radix {
+        Some((radix, name)) => {
+            let digits = &bytes[2..len];
+            let separated = separators_between(digits, |c| c.is_ascii_alphanumeric());
+
+            match u64::from_str_radix(&s[2..].replace('_', ""), radix) {
+                Ok(n) if separated && digits.first() != Some(&b'_') => n as f32,
+                _ => return Err(format!("Invalid {} number: \"{}\"", name, s)),
+            }
+        },
+        None => {
+            let decimal: String = bytes[..len].iter().filter_map(|c| match *c {
+                b'_' => None,
+                c if is(c, format.decimal) => Some('.'),
+                c if format.thousands.is_some_and(|t| is(c, t)) => None,
+                c => Some(c as char),
+            }).collect();
+
+            match decimal.parse() {
+                Ok(n) if separators_between(&bytes[..len], |c| c.is_ascii_digit()) && grouped(&bytes[..len], format) => n,
+                _ if len == 1 => return Err(format!("Unexpected character: \"{}\"", s)),
+                _ => return Err(format!("Invalid number format: \"{}\"", s)),
+            }
+        },
+    };
+
+    Ok(Lexed::Token(Token::Number(n), len))
+}
+/// Whether the thousands separators are only before the decimal separator and three digits apart.
+fn grouped(bytes: &[u8], format: &NumberFormat) -> bool {
+
+    let Some(t) = format.thousands else { return true };
+
+    let integer = bytes.iter().position(|c| is(*c, format.decimal) || !(c.is_ascii_digit() || *c == b'_' || is(*c, t))).unwrap_or(bytes.len());
+
+    if !bytes.iter().any(|c| is(*c, t)) {
+        return true;
+    }
+    if bytes[integer..].iter().any(|c| is(*c, t)) {
+        return false;
     }
+    bytes[..integer].split(|c| is(*c, t)).enumerate().all(|(i, group)| {
+        let digits = group.iter().filter(|c| c.is_ascii_digit()).count();
+        if i == 0 { (1..=3).contains(&digits) } else { digits == 3 }
+    })
 }
-#[derive(Clone, Copy)]
+/// Whether the byte is the separator, which has to be ASCII.
+fn is(c: u8, separator: char) -> bool {
+    c.is_ascii() && c as char == separator
+}
+/// Whether every `_` is between two digits.
+fn separators_between(bytes: &[u8], digit: impl Fn(u8) -> bool) -> bool {
+    bytes.iter().enumerate().all(|(i, c)| *c != b'_' || (i > 0 && digit(bytes[i - 1]) && bytes.get(i + 1).is_some_and(|c| digit(*c))))
+}
+fn run_length(bytes: &[u8], f: impl Fn(u8) -> bool) -> usize {
+    bytes.iter().position(|c| !f(*c)).unwrap_or(bytes.len())
+}
+#[derive(Clone)]
 pub enum Token {
     Number(f32),
     Operator(Operator),
     Bracket(bool),
+    /// `[` and `]` of a list like `[1, 2]`, see `matrix`.
+    List(bool),
+    Variable(String),
+    Function(Function),
+    Comma,
+    /// A string in double quotes, see `strings`.
+    Str(String),
 }
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Operator {
     Plus,
     Minus,
     Mul,
-    Div
+    Div,
+    Pow,
+    /// The matrix product `@`, a product of numbers.
+    MatMul,
+    /// The comparisons `==`, `!=`, `<`, `<=`, `>` and `>=`, see `strings`. A number is 1 if they hold and 0 otherwise.
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
 }
 pub fn token_to_string(t: &Token) -> String {
     match t {
         Token::Number(n) => format!("{n}"),
-        Token::Operator(o) => format!("{}", operator_to_string(o)),
+        Token::Operator(o) => operator_to_string(o).to_string(),
         Token::Bracket(b) => {
             if *b { "(".to_string() }
             else { ")".to_string() }
         },
+        Token::List(b) => {
+            if *b { "[".to_string() }
+            else { "]".to_string() }
+        },
+        Token::Variable(name) => name.clone(),
+        Token::Function(f) => function_to_string(f).to_string(),
+        Token::Comma => ",".to_string(),
+        Token::Str(text) => quote(text),
+    }
+}
+/// Writes the string in double quotes, escaped as the lexer reads it.
+pub(crate) fn quote(text: &str) -> String {
+
+    let mut s = String::with_capacity(text.len() + 2);
+    s.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            '\n' => s.push_str("\\n"),
+            '\t' => s.push_str("\\t"),
+            '\r' => s.push_str("\\r"),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+pub fn operator_to_string(o: &Operator) -> &'static str {
+    match o {
+        Operator::Plus => "+",
+        Operator::Minus => "-",
+        Operator::Mul => "*",
+        Operator::Div => "/",
+        Operator::Pow => "^",
+        Operator::MatMul => "@",
+        Operator::Equal => "==",
+        Operator::NotEqual => "!=",
+        Operator::Less => "<",
+        Operator::LessEqual => "<=",
+        Operator::Greater => ">",
+        Operator::GreaterEqual => ">=",
+    }
+}
+/// Whether the operator compares its operands, comparisons bind weakest.
+pub fn is_comparison(o: &Operator) -> bool {
+    precedence(o) < 0
+}
+/// Binding strength of an operator, higher binds tighter.
+pub fn precedence(o: &Operator) -> i32 {
+    match o {
+        Operator::Equal | Operator::NotEqual | Operator::Less | Operator::LessEqual | Operator::Greater | Operator::GreaterEqual => -1,
+        Operator::Plus | Operator::Minus => 0,
+        Operator::Mul | Operator::Div | Operator::MatMul => 1,
+        Operator::Pow => 2,
     }
 }
-pub fn operator_to_string(o: &Operator) -> char {
+pub fn apply_operator(o: &Operator, left: f32, right: f32) -> f32 {
     match o {
-        Operator::Plus => '+',
-        Operator::Minus => '-',
-        Operator::Mul => '*',
-        Operator::Div => '/',
+        Operator::Plus => left + right,
+        Operator::Minus => left - right,
+        Operator::Mul | Operator::MatMul => left * right,
+        Operator::Div => left / right,
+        Operator::Pow => left.powf(right),
+        Operator::Equal => (left == right) as i32 as f32,
+        Operator::NotEqual => (left != right) as i32 as f32,
+        Operator::Less => (left < right) as i32 as f32,
+        Operator::LessEqual => (left <= right) as i32 as f32,
+        Operator::Greater => (left > right) as i32 as f32,
+        Operator::GreaterEqual => (left >= right) as i32 as f32,
+    }
+}
+
+/// Returns a single byte per read, so every token is split between reads.
+#[cfg(test)]
+struct Bytes<'a>(&'a [u8]);
+
+#[cfg(test)]
+impl Read for Bytes<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.0.len().min(buf.len()).min(1);
+        buf[..len].copy_from_slice(&self.0[..len]);
+        self.0 = &self.0[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn streaming_tokens() {
+    use crate::{eval_exp, eval_exp_in, eval_read, script::Scope};
+
+    let exp = "12.5 * max(3, 4_0) - abc+ sqrt(16)";
+
+    let read: Vec<Span> = ReadTokens::new(Bytes(exp.as_bytes())).map(|t| t.unwrap().1).collect();
+    assert_eq!(read, parse_exp_spans(exp).unwrap().1);
+    assert_eq!(read[0], 0..4);
+
+    let mut scope = Scope::new();
+    scope.set("abc", 2.0);
+
+    for exp in ["1 +2* 3 +4", "2^3^2", "-2^2 * 3", "(1 + 2 * 3) * 4 (1 + 2 + 3) * 5", "2max(1, -pi) + sqrt(16)", "min(2, max(3 e, 1)) ^ 2"] {
+        assert_eq!(eval_read(Bytes(exp.as_bytes()), &scope), eval_exp(exp), "{}", exp);
+    }
+    assert_eq!(eval_read(exp.as_bytes(), &scope), eval_exp_in(exp, &scope));
+
+    assert_eq!(eval_read(Bytes(b"1 + 2.3.4"), &scope), Err("Invalid number format: \"2.3.4\"".to_string()));
+    assert_eq!(eval_read(Bytes(b"(1 + 2"), &scope), Err("Unclosed bracket/s".to_string()));
+    assert_eq!(eval_read(Bytes(b"1 + y"), &scope), Err("Unknown variable: \"y\"".to_string()));
+    assert_eq!(eval_read(Bytes(b""), &scope), Err("Empty expression".to_string()));
+}
+#[test]
+fn number_literals() {
+    use crate::{eval_exp, eval_read, script::Scope};
+
+    let scope = Scope::new();
+
+    for (exp, n) in [("1e-3", 1e-3), ("6.02E23", 6.02e23), ("2.5e+2", 250.0), ("1_000.5", 1000.5), ("0xFF", 255.0), ("0o17", 15.0), ("0b1010", 10.0), ("0x_ff", 0.0)] {
+        let parsed = parse_exp(exp).map(|t| match t[..] { [Token::Number(n)] => n, _ => f32::NAN });
+        assert_eq!(parsed, if exp == "0x_ff" { Err("Invalid hexadecimal number: \"0x_ff\"".to_string()) } else { Ok(n) }, "{}", exp);
+        assert_eq!(eval_read(Bytes(exp.as_bytes()), &scope), eval_exp(exp), "{}", exp);
+    }
+    assert_eq!(eval_exp("2exp(0) + 3e1"), Ok(32.0));
+
+    for (exp, err) in [
+        ("1e", "Invalid number format: \"1e\", the exponent has no digits"),
+        ("1e+ 2", "Invalid number format: \"1e+\", the exponent has no digits"),
+        ("0x", "Invalid hexadecimal number: \"0x\""),
+        ("0b102", "Invalid binary number: \"0b102\""),
+        ("1.2.3", "Invalid number format: \"1.2.3\""),
+        ("1__000", "Invalid number format: \"1__000\""),
+        ("2 _ 3", "Unexpected character: \"_\""),
+    ] {
+        assert_eq!(parse_exp(exp).err().unwrap(), err, "{}", exp);
+        assert_eq!(eval_read(Bytes(exp.as_bytes()), &scope).unwrap_err(), err, "{}", exp);
+    }
+}
+#[test]
+fn unicode_input() {
+    use crate::{eval_exp_in, eval_read, script::Scope};
+
+    let mut scope = Scope::new();
+    scope.set("abc", 2.0);
+
+    for (exp, ascii) in [
+        ("3 × 4 ÷ 2 − 1·2", "3 * 4 / 2 - 1*2"),
+        ("2π\t+\u{a0}√2 + √(9)", "2pi + sqrt(2) + sqrt(9)"),
+        ("abc² + 2³¹ − 10⁻¹", "abc^2 + 2^31 - 10^(-1)"),
+        ("√abc⁴", "sqrt(abc)^4"),
+    ] {
+        assert_eq!(eval_exp_in(exp, &scope), eval_exp_in(ascii, &scope), "{}", exp);
+        assert_eq!(eval_read(Bytes(exp.as_bytes()), &scope), eval_exp_in(ascii, &scope), "{}", exp);
+    }
+    assert_eq!(parse_exp_spans("x²").unwrap().1, [0..1, 1..3, 1..3]);
+
+    for (input, err) in [("2 ∑ 3", "Unexpected character: \"∑\""), ("2⁻", "Expected a superscript digit after \"⁻\"")] {
+        assert_eq!(parse_exp(input).err().unwrap(), err);
+        assert_eq!(eval_read(Bytes(input.as_bytes()), &scope).unwrap_err(), err);
+    }
+    assert_eq!(eval_read(Bytes(b"1 + \xff"), &scope).unwrap_err(), "Invalid UTF-8 in input");
+    assert_eq!(eval_read(Bytes(b"1 + \xc3"), &scope).unwrap_err(), "Invalid UTF-8 in input");
+}
+#[test]
+fn string_evaluator_tokens() {
+    use crate::{eval_exp, eval_exp_in, eval_read, script::Scope};
+
+    // the functions of the other evaluators are variables unless they are called
+    let mut names = Scope::new();
+    for name in ["num", "len", "re", "im", "det", "dot"] {
+        names.set(name, 2.0);
+    }
+    for (exp, n) in [("num * 3", 6.0), ("len + re - im", 2.0), ("3det", 6.0), ("dot^2 dot", 8.0)] {
+        assert_eq!(eval_exp_in(exp, &names), Ok(n), "{}", exp);
+        assert_eq!(eval_read(Bytes(exp.as_bytes()), &names), Ok(n), "{}", exp);
+    }
+    for exp in ["det (4)", "2 re  (3)"] {
+        assert!(matches!(parse_exp(exp).unwrap()[..], [.., Token::Function(_), Token::Bracket(true), _, Token::Bracket(false)]), "{}", exp);
+        assert_eq!(eval_read(Bytes(exp.as_bytes()), &names).unwrap_err(), eval_exp(exp).unwrap_err(), "{}", exp);
+    }
+
+    for (input, err) in [("\"ab", "Unclosed string"), ("\"a\\é\"", "Unknown escape in string: \"\\é\"")] {
+        assert_eq!(parse_exp(input).err().unwrap(), err);
+        assert_eq!(eval_read(Bytes(input.as_bytes()), &Scope::new()).unwrap_err(), err);
+    }
+    for exp in ["1<=2", "x != 2 == 1", "3>", "2 ≥ 1"] {
+        let read: Vec<String> = ReadTokens::new(Bytes(exp.as_bytes())).map(|t| token_to_string(&t.unwrap().0)).collect();
+        assert_eq!(read, parse_exp(exp).unwrap().iter().map(token_to_string).collect::<Vec<_>>(), "{}", exp);
     }
+    let read: Vec<String> = ReadTokens::new(Bytes("\"a\\\"ü\" + \"\"".as_bytes())).map(|t| token_to_string(&t.unwrap().0)).collect();
+    assert_eq!(read, ["\"a\\\"ü\"", "+", "\"\""]);
 }
@@ -1,186 +1,283 @@
 use std::mem::replace;
 
-use crate::{eval_exp, token_parse::{operator_to_string, Operator, Token}, DEBUG};
+use crate::{functions::{call_function, Function, MAX_ARGUMENTS}, token_parse::{apply_operator, is_comparison, Operator, Token}};
 
 pub fn evaluate_exp(tokens: &[Token]) -> f32 {
-
-    let mut parser = Parser::new(tokens.to_vec());
-    create_eval_tree_recursive(&mut parser)
+    create_tree(tokens, &[]).evaluate(&[], &mut Scratch::default())
 }
-fn create_eval_tree_recursive(parser: &mut Parser) -> f32 {
+/// Builds the tree of a whole expression. Brackets become subtrees and every
+/// variable in `variables` becomes a slot, other variables must be resolved beforehand.
+/// 
+/// The enclosing levels of open brackets are kept on a stack, so any depth of nesting can be built.
+pub(crate) fn create_tree(tokens: &[Token], variables: &[&str]) -> TokenTree {
 
     let mut tree = TokenTree::new();
     let mut operator = Operator::Plus;
+    let mut function = None;
+
+    // the levels around open brackets, with the operator before the bracket,
+    // and the function it calls with the index of its first argument in `args`
+    let mut levels = Vec::new();
+    // the roots of the finished arguments of open function calls
+    let mut args = Vec::new();
+    // the left side of a comparison in the current level, the right side is built as a level of its own
+    let mut compared = None;
 
-    while parser.has_next() {
-        match parser.consume() {
+    for t in tokens {
+        match t {
             Token::Number(n) => {
-                tree.add_next(operator, *n);
+                tree.add_next(operator, Node::Number(*n));
+            },
+            Token::Variable(name) => {
+                let slot = variables.iter().position(|v| v == name).expect("variables are resolved before evaluation");
+                tree.add_next(operator, Node::Variable(slot));
+            },
+            Token::Function(f) => {
+                function = Some(*f);
+            },
+            Token::List(_) => unreachable!("lists are validated"),
+            Token::Str(_) => unreachable!("strings are validated"),
+            Token::Operator(o) if is_comparison(o) => {
+                tree.end_comparison(compared.take());
+                compared = Some((replace(&mut tree.level, Level::new()), *o));
             },
             Token::Operator(o) => {
                 operator = *o;
             },
+            Token::Comma => {
+                tree.end_comparison(compared.take());
+                args.push(tree.tokens[tree.level.base]);
+                tree.level = Level::new();
+            },
             Token::Bracket(b) => {
-                if *b { tree.add_next(operator, create_eval_tree_recursive(parser)); }
-                else {
-                    if DEBUG { tree.print(); }
-                    return tree.evaluate_tree(); 
+                if *b {
+                    let outer = replace(&mut tree.level, Level::new());
+                    levels.push((outer, operator, function.take().map(|f| (f, args.len())), compared.take()));
+                    continue;
                 }
+
+                tree.end_comparison(compared.take());
+                let (outer, o, call, outer_compared) = levels.pop().expect("brackets are validated");
+                compared = outer_compared;
+                let inner = replace(&mut tree.level, outer);
+
+                match call {
+                    Some((f, first)) => {
+                        args.push(tree.tokens[inner.base]);
+                        let call_args = args.split_off(first);
+                        tree.add_call(o, f, call_args);
+                    },
+                    None => tree.add_tree(o, inner),
+                }
+                operator = o;
             },
         }
     }
-    if DEBUG { tree.print(); }
-    return tree.evaluate_tree();
+    tree.end_comparison(compared);
+    tree
 }
 
-struct Parser {
-    tokens: Box<[Token]>,
-    index: usize,
+/// The nodes of all levels are kept in one vector, the root is at `level.base`.
+pub(crate) struct TokenTree {
+    tokens: Vec<Node>,
+    level: Level,
 }
-impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens: tokens.into(), index: 0 }
-    }
-    fn consume(&mut self) -> &Token {
-        self.index += 1;
-        &self.tokens[self.index - 1]
+/// The part of the tree inside a pair of brackets or a function argument.
+/// `term` is the last product and `last` the last power, where the next operator is attached.
+#[derive(Clone, Copy)]
+struct Level {
+    base: usize,
+    term: usize,
+    last: usize,
+    empty: bool,
+}
+impl Level {
+    fn new() -> Self {
+        Level { base: 0, term: 0, last: 0, empty: true }
     }
-    fn has_next(&self) -> bool {
-        self.tokens.len() > self.index
+    fn at(index: usize) -> Self {
+        Level { base: index, term: index, last: index, empty: false }
     }
 }
-
-struct TokenTree {
-    tokens: Vec<Node>,
-    base: usize,
-    last: usize,
-} 
 impl TokenTree {
     fn new() -> Self {
-        TokenTree { tokens: Vec::new(), base: 0, last: 0 }
+        TokenTree { tokens: Vec::new(), level: Level::new() }
     }
-    fn add_last(&mut self, o: Operator, n: f32) {
+    fn add_term(&mut self, o: Operator, n: Node) {
         let node = Node::Operator( NodeOp { operator: o, left: self.tokens.len(), right: self.tokens.len() + 1 } );
 
-        let last = replace(&mut self.tokens[self.last], node);
+        let term = replace(&mut self.tokens[self.level.term], node);
+
+        self.tokens.push(term);
+        self.tokens.push(n);
+
+        self.level.last = self.tokens.len() - 1;
+    }
+    fn add_last(&mut self, o: Operator, n: Node) {
+        let node = Node::Operator( NodeOp { operator: o, left: self.tokens.len(), right: self.tokens.len() + 1 } );
+
+        let last = replace(&mut self.tokens[self.level.last], node);
 
         self.tokens.push(last);
-        self.tokens.push(Node::Number(n));
+        self.tokens.push(n);
 
-        self.last = self.tokens.len() - 1;
+        self.level.last = self.tokens.len() - 1;
     }
-    fn add_base(&mut self, o: Operator, n: f32) {
+    fn add_base(&mut self, o: Operator, n: Node) {
 
-        let node = Node::Operator( NodeOp { operator: o, left: self.base, right: self.tokens.len() } );
+        let node = Node::Operator( NodeOp { operator: o, left: self.level.base, right: self.tokens.len() } );
         
 
-        self.tokens.push( Node::Number(n) );
+        self.tokens.push(n);
         self.tokens.push(node);
 
-        self.base = self.tokens.len() - 1;
-        self.last = self.tokens.len() - 2;
+        self.level.base = self.tokens.len() - 1;
+        self.level.term = self.tokens.len() - 2;
+        self.level.last = self.tokens.len() - 2;
     }
-    fn add_next(&mut self, o: Operator, n: f32) {
+    fn add_next(&mut self, o: Operator, n: Node) {
 
-        if self.tokens.len() == 0 {
-            self.tokens.push(Node::Number(n));
+        if self.level.empty {
+            self.tokens.push(n);
+            self.level = Level::at(self.tokens.len() - 1);
             return;
         }
 
         match o {
             Operator::Plus | Operator::Minus => self.add_base(o, n),
-            Operator::Mul | Operator::Div => self.add_last(o, n),
+            Operator::Mul | Operator::Div | Operator::MatMul => self.add_term(o, n),
+            Operator::Pow => self.add_last(o, n),
+            _ => unreachable!("comparisons end the level"),
         }
     }
-    fn evaluate_tree(&mut self) -> f32 {
+    /// Compares the left side of a comparison with the finished level, which becomes the result of the level.
+    fn end_comparison(&mut self, compared: Option<(Level, Operator)>) {
+        if let Some((left, o)) = compared {
+            self.tokens.push(Node::Operator( NodeOp { operator: o, left: left.base, right: self.level.base } ));
+            self.level = Level::at(self.tokens.len() - 1);
+        }
+    }
+    /// Adds the finished level of a bracket, its nodes are already in the tree.
+    fn add_tree(&mut self, o: Operator, tree: Level) {
+
+        if self.level.empty {
+            self.level = Level::at(tree.base);
+            return;
+        }
 
+        self.add_next(o, self.tokens[tree.base]);
+    }
+    fn add_call(&mut self, o: Operator, f: Function, args: Vec<Node>) {
+
+        let call = Node::Call( NodeCall { function: f, first: self.tokens.len(), count: args.len() } );
+
+        self.tokens.extend(args);
+        self.add_next(o, call);
+    }
+    /// Evaluates the tree without modifying it, trees too big to evaluate recursively use the stacks in `scratch`.
+    pub(crate) fn evaluate(&self, vars: &[f32], scratch: &mut Scratch) -> f32 {
+
+        // a tree this small can't be deep enough to overflow the stack
         if self.tokens.len() < 10_000 {
-            self.evaluate_node_stack(&self.tokens[self.base])
-        }   
+            self.evaluate_node_stack(&self.tokens[self.level.base], vars)
+        }
         else {
-            self.evaluate_node_heap()
+            self.evaluate_node_heap(vars, scratch)
         }
     }
-    fn evaluate_node_stack(&self, node: &Node) -> f32 {
+    fn evaluate_node_stack(&self, node: &Node, vars: &[f32]) -> f32 {
         match node {
             Node::Number(n) => *n,
+            Node::Variable(slot) => vars[*slot],
             Node::Operator(op) => {
-                let left = self.evaluate_node_stack(&self.tokens[op.left]);
-                let right = self.evaluate_node_stack(&self.tokens[op.right]);
-                match op.operator {
-                    Operator::Plus => left + right,
-                    Operator::Minus => left - right,
-                    Operator::Mul => left * right,
-                    Operator::Div => left / right,
+                let left = self.evaluate_node_stack(&self.tokens[op.left], vars);
+                let right = self.evaluate_node_stack(&self.tokens[op.right], vars);
+                apply_operator(&op.operator, left, right)
+            },
+            Node::Call(call) => {
+                let mut args = [0.0; MAX_ARGUMENTS];
+                for (i, arg) in args[..call.count].iter_mut().enumerate() {
+                    *arg = self.evaluate_node_stack(&self.tokens[call.first + i], vars);
                 }
+                call_function(&call.function, &args[..call.count])
             },
         }
     }
-    fn evaluate_node_heap(&mut self) -> f32 {
-        let mut nodes = Vec::with_capacity(self.tokens.len() / 2);
-        
-        match self.tokens[self.base].clone() {
-            Node::Number(n) => return n,
-            Node::Operator(op) => nodes.push((self.base, op)),
-        }
+    /// Evaluates the tree with explicit stacks of nodes and values instead of recursion.
+    fn evaluate_node_heap(&self, vars: &[f32], scratch: &mut Scratch) -> f32 {
 
-        loop {
-            let (index, op) = nodes.last().unwrap().clone();
-            match (self.tokens[op.left].clone(), self.tokens[op.right].clone()) {
-                (Node::Number(n1), Node::Number(n2)) => {
-                    let res = match op.operator {
-                        Operator::Plus => n1 + n2,
-                        Operator::Minus => n1 - n2,
-                        Operator::Mul => n1 * n2,
-                        Operator::Div => n1 / n2,
-                    };
-
-                    if nodes.len() == 0 {
-                        return res;
-                    }
+        let Scratch { nodes, values } = scratch;
+        nodes.clear();
+        values.clear();
+
+        nodes.push((self.level.base, false));
 
-                    self.tokens[index] = Node::Number(res);
-                    nodes.pop();
+        while let Some((index, ready)) = nodes.pop() {
+            match self.tokens[index] {
+                Node::Number(n) => values.push(n),
+                Node::Variable(slot) => values.push(vars[slot]),
+                Node::Operator(op) => {
+                    if ready {
+                        let right = values.pop().unwrap();
+                        let left = values.last_mut().unwrap();
+                        *left = apply_operator(&op.operator, *left, right);
+                    }
+                    else {
+                        nodes.push((index, true));
+                        nodes.push((op.right, false));
+                        nodes.push((op.left, false));
+                    }
                 },
-                (Node::Number(_), Node::Operator(op2)) => {
-                    nodes.push((op.right, op2));
-                }
-                    
-                (Node::Operator(op1), Node::Number(_)) => {
-                    nodes.push((op.left, op1));
-                }
-                (Node::Operator(op1), Node::Operator(op2)) => { 
-                    nodes.push((op.left, op1)); 
-                    nodes.push((op.right, op2)); 
+                Node::Call(call) => {
+                    if ready {
+                        let start = values.len() - call.count;
+                        let res = call_function(&call.function, &values[start..]);
+                        values.truncate(start);
+                        values.push(res);
+                    }
+                    else {
+                        nodes.push((index, true));
+                        nodes.extend((call.first..call.first + call.count).rev().map(|i| (i, false)));
+                    }
                 },
             }
         }
+
+        values.pop().unwrap()
     }
-    pub fn print(&self) {
-        println!("TREE - base: {}, last:  {}", self.base, self.last);
-        for (i, n) in self.tokens.iter().enumerate() {
-            print!("{} ", i);
-            match n {
-                Node::Number(n) => println!("N: {}", n),
-                Node::Operator(op) => println!("Op: {}, {}, {}", operator_to_string(&op.operator), op.left, op.right),
-            }
-        }
-    }
 }
-#[derive(Clone)]
+/// The stacks used to evaluate trees without recursion, they are kept between
+/// evaluations so only the first evaluation of a big tree allocates.
+#[derive(Default)]
+pub struct Scratch {
+    // nodes to visit, with whether their children are already evaluated
+    nodes: Vec<(usize, bool)>,
+    values: Vec<f32>,
+}
+#[derive(Clone, Copy)]
 enum Node {
     Number(f32),
+    Variable(usize),
     Operator(NodeOp),
+    Call(NodeCall),
 }
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 struct NodeOp {
     operator: Operator,
     left: usize,
     right: usize,
 }
+#[derive(Clone, Copy)]
+struct NodeCall {
+    function: Function,
+    first: usize,
+    count: usize,
+}
 
 #[test]
 fn basic_evaluation() {
+    use crate::eval_exp;
+
     assert_eq!( eval_exp("1 +2* 3 +4").unwrap(), 11.0);
     assert!( eval_exp("-1 *2 +3").unwrap() == 1.0);
     assert!( eval_exp("2 / 4 / 2").unwrap() == 0.25);
@@ -191,4 +288,78 @@ fn basic_evaluation() {
     assert_eq!(eval_exp("(1 + 2 * 3) * 4 (1 + 2 + 3) * 5").unwrap(), 840.0);
 
     assert_eq!( eval_exp("(1+2)(3+4)").unwrap(), 21.0);
-}
\ No newline at end of file
+
+    assert_eq!( eval_exp("((1+2))").unwrap(), 3.0);
+    assert_eq!( eval_exp("2^3^2").unwrap(), 512.0);
+    assert_eq!( eval_exp("-2^2 * 3").unwrap(), -12.0);
+    assert_eq!( eval_exp("2pi").unwrap(), 2.0 * std::f32::consts::PI);
+    assert_eq!( eval_exp("sqrt(16) + 2max(1, 3) - min(-(2), 1)").unwrap(), 12.0);
+}
+#[test]
+fn tree_evaluation() {
+    use crate::{token_parse::parse_exp, validation::{add_implicit_tokens, validate_tokens}};
+
+    for (exp, expected) in [("2 / 4 / 2", 0.25), ("1 - 2 - 3", -4.0), ("2^3^2", 512.0), ("2 * 3^2 / 2", 9.0), ("-(1+2)4", -12.0), ("2max(sqrt(4), 1 + 2)^2", 18.0), ("abs(-3)", 3.0)] {
+        let mut tokens = parse_exp(exp).unwrap();
+        validate_tokens(&tokens).unwrap();
+        add_implicit_tokens(&mut tokens);
+
+        assert_eq!(evaluate_exp(&tokens), expected, "{}", exp);
+    }
+}
+#[test]
+fn deep_nesting() {
+    use crate::{bytecode::evaluate_bytecode, compiled::CompiledExpr, linear_eval::linear_eval, prepare_tokens, prepare_tokens_with_depth};
+
+    let n = 100_000;
+
+    for (exp, expected) in [
+        (format!("{}1{}", "(".repeat(n), "+1)".repeat(n)), n as f32 + 1.0),
+        (format!("{}1{}", "1+(".repeat(n), ")".repeat(n)), n as f32 + 1.0),
+        (format!("{}2{}", "max(1, ".repeat(n), ")".repeat(n)), 2.0),
+    ] {
+        assert_eq!(prepare_tokens(&exp).err().unwrap(), "Brackets nested deeper than 10000 levels");
+
+        let tokens = prepare_tokens_with_depth(&exp, n).unwrap();
+
+        assert_eq!(evaluate_exp(&tokens), expected);
+        assert_eq!(evaluate_bytecode(&tokens), expected);
+        assert_eq!(linear_eval(tokens), expected);
+    }
+
+    let exp = format!("{}x{}", "(1+".repeat(5000), ")".repeat(5000));
+    let compiled = CompiledExpr::new(&exp, &["x"]).unwrap();
+    assert_eq!(compiled.eval(&[1.0]), 5001.0);
+
+    let mut scratch = Scratch::default();
+    assert_eq!(compiled.eval_with(&[1.0], &mut scratch), 5001.0);
+    assert_eq!(compiled.eval_with(&[2.0], &mut scratch), 5002.0);
+}
+#[test]
+fn token_comparisons() {
+    use crate::{batch::eval_batch, bytecode::{compile, evaluate_bytecode}, linear_eval::linear_eval, parallel::{parallel_eval, ParallelOptions}, token_parse::parse_exp, validation::add_implicit_tokens};
+
+    // comparisons are only validated in the string evaluator, the token evaluators take them as they come
+    let sum = format!("1{}", "+1".repeat(5000));
+
+    for (exp, expected) in [
+        ("1 < 2".to_string(), 1.0),
+        ("1 + 2 * 3 >= 2^3".to_string(), 0.0),
+        ("2(1 == 1) + max(1 != 2, 3 <= 2)".to_string(), 3.0),
+        ("(2 > 1) - (1 > 2)".to_string(), 1.0),
+        ("1 < -2".to_string(), 0.0),
+        (format!("{} == {}", sum, sum), 1.0),
+    ] {
+        let mut tokens = parse_exp(&exp).unwrap();
+        add_implicit_tokens(&mut tokens);
+
+        let mut out = [0.0f32];
+        eval_batch(&compile(&tokens, &[]), &[], &mut out);
+
+        assert_eq!(evaluate_exp(&tokens), expected, "{}", exp);
+        assert_eq!(linear_eval(tokens.clone()), expected, "{}", exp);
+        assert_eq!(evaluate_bytecode(&tokens), expected, "{}", exp);
+        assert_eq!(parallel_eval(&tokens, &ParallelOptions { threads: 2, deterministic: true }), expected, "{}", exp);
+        assert_eq!(out[0], expected, "{}", exp);
+    }
+}
@@ -0,0 +1,367 @@
+use std::fmt;
+
+use crate::{ast::{ast_from_tokens, check_call, Expr, ExprKind, UnaryOp}, functions::{function_to_string, Function}, script::Scope, token_parse::{is_comparison, apply_operator, parse_exp_spans, Operator, Span, Token}, validation::{Validator, COMPARISONS, LISTS, STRINGS}};
+
+/// Exponents of the SI base units kg, m, s, A, K, mol and cd.
+pub type Dims = [i8; 7];
+
+const NONE: Dims = [0; 7];
+const BASE_UNITS: [&str; 7] = ["kg", "m", "s", "A", "K", "mol", "cd"];
+
+/// A value in SI base units with its dimensions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantity {
+    pub value: f32,
+    pub dims: Dims,
+}
+impl Quantity {
+    pub fn number(value: f32) -> Self {
+        Quantity { value, dims: NONE }
+    }
+}
+/// Prints the value in SI base units, like `3.5 kg*m/s^2`.
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.dims {
+            NONE => write!(f, "{}", self.value),
+            dims => write!(f, "{} {}", self.value, unit_name(&dims)),
+        }
+    }
+}
+/// A result in the unit it was converted to, or in SI base units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    pub value: f32,
+    pub unit: String,
+}
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.unit.is_empty() {
+            write!(f, "{}", self.value)
+        }
+        else {
+            write!(f, "{} {}", self.value, self.unit)
+        }
+    }
+}
+/// The factor to SI base units and the dimensions of a unit.
+pub fn unit(name: &str) -> Option<(f32, Dims)> {
+
+    const KG: Dims = [1, 0, 0, 0, 0, 0, 0];
+    const M: Dims = [0, 1, 0, 0, 0, 0, 0];
+    const S: Dims = [0, 0, 1, 0, 0, 0, 0];
+    const VOLUME: Dims = [0, 3, 0, 0, 0, 0, 0];
+    const FORCE: Dims = [1, 1, -2, 0, 0, 0, 0];
+    const ENERGY: Dims = [1, 2, -2, 0, 0, 0, 0];
+    const POWER: Dims = [1, 2, -3, 0, 0, 0, 0];
+
+    Some(match name {
+        "m" => (1.0, M),
+        "km" => (1e3, M),
+        "cm" => (1e-2, M),
+        "mm" => (1e-3, M),
+        "um" => (1e-6, M),
+        "nm" => (1e-9, M),
+        // `in` converts, an inch is written out
+        "inch" => (0.0254, M),
+        "ft" => (0.3048, M),
+        "yd" => (0.9144, M),
+        "mi" => (1609.344, M),
+        "kg" => (1.0, KG),
+        "g" => (1e-3, KG),
+        "mg" => (1e-6, KG),
+        "lb" => (0.453_592_37, KG),
+        "s" => (1.0, S),
+        "ms" => (1e-3, S),
+        "us" => (1e-6, S),
+        "min" => (60.0, S),
+        "h" => (3600.0, S),
+        "day" => (86400.0, S),
+        "A" => (1.0, [0, 0, 0, 1, 0, 0, 0]),
+        "K" => (1.0, [0, 0, 0, 0, 1, 0, 0]),
+        "mol" => (1.0, [0, 0, 0, 0, 0, 1, 0]),
+        "cd" => (1.0, [0, 0, 0, 0, 0, 0, 1]),
+        "Hz" => (1.0, [0, 0, -1, 0, 0, 0, 0]),
+        "L" => (1e-3, VOLUME),
+        "N" => (1.0, FORCE),
+        "J" => (1.0, ENERGY),
+        "kJ" => (1e3, ENERGY),
+        "kWh" => (3.6e6, ENERGY),
+        "W" => (1.0, POWER),
+        "kW" => (1e3, POWER),
+        "Pa" => (1.0, [1, -1, -2, 0, 0, 0, 0]),
+        "V" => (1.0, [1, 2, -3, -1, 0, 0, 0]),
+        _ => return None,
+    })
+}
+/// Names the dimensions with SI base units, like `kg*m/s^2`.
+fn unit_name(dims: &Dims) -> String {
+
+    let join = |positive: bool| {
+        let units: Vec<String> = dims.iter().zip(BASE_UNITS).filter(|(d, _)| (**d > 0) == positive && **d != 0).map(|(d, unit)| {
+            match d.abs() {
+                1 => unit.to_string(),
+                d => format!("{}^{}", unit, d),
+            }
+        }).collect();
+        units.join("*")
+    };
+
+    match (join(true), join(false)) {
+        (numerator, denominator) if denominator.is_empty() => numerator,
+        (numerator, denominator) if numerator.is_empty() => format!("1/{}", denominator),
+        (numerator, denominator) => format!("{}/{}", numerator, denominator),
+    }
+}
+fn dims_name(dims: &Dims) -> String {
+    match dims {
+        &NONE => "a number".to_string(),
+        dims => unit_name(dims),
+    }
+}
+/// Evaluates the input with units, `3 km / 15 min` is `3.3333333 m/s`. A unit after a number
+/// belongs to it, with its power as in `3 m^2`. Ends with `to` or `in` and a unit like
+/// `km/h`, the result is converted to that unit, otherwise it is in SI base units. Units made
+/// of other units are never picked for the result, `3 km / 15 min to km/h` is needed for `12 km/h`.
+///
+/// Names resolve to variables of the scope before units, and adding or comparing
+/// quantities of different dimensions is an error.
+pub fn eval_units(input: &str, scope: &Scope) -> Result<Measurement, String> {
+
+    let (mut tokens, mut spans) = parse_exp_spans(input)?;
+
+    // the conversion binds looser than everything else
+    let mut depth = 0;
+    let to = tokens.iter().position(|t| {
+        match t {
+            Token::Bracket(open) => depth += if *open { 1 } else { -1 },
+            Token::Variable(name) => return depth == 0 && (name == "to" || name == "in"),
+            _ => {},
+        }
+        false
+    });
+
+    let Some(to) = to else {
+        let q = evaluate_quantity(&units_ast(tokens, spans)?, scope)?;
+        return Ok(Measurement { value: q.value, unit: unit_name(&q.dims) });
+    };
+
+    let target_input = input[spans[to].end..].trim();
+
+    let target_tokens = tokens.split_off(to + 1);
+    let target_spans = spans.split_off(to + 1);
+    tokens.pop();
+    spans.pop();
+
+    let q = evaluate_quantity(&units_ast(tokens, spans)?, scope)?;
+    let target = evaluate_quantity(&units_ast(target_tokens, target_spans)?, &Scope::default())?;
+
+    if q.dims != target.dims {
+        return Err(format!("Dimension mismatch: cannot convert {} to {}", dims_name(&q.dims), target_input));
+    }
+
+    Ok(Measurement { value: q.value / target.value, unit: target_input.to_string() })
+}
+/// Parses the tokens with units after numbers grouped with them, like `(3 * m^2)`.
+fn units_ast(tokens: Vec<Token>, spans: Vec<Span>) -> Result<Expr, String> {
+
+    // `min` is minutes unless it is called
+    let tokens: Vec<Token> = tokens.iter().enumerate().map(|(i, t)| match t {
+        Token::Function(Function::Min) if !matches!(tokens.get(i + 1), Some(Token::Bracket(true))) => Token::Variable("min".to_string()),
+        t => t.clone(),
+    }).collect();
+
+    let mut grouped = (Vec::with_capacity(tokens.len()), Vec::with_capacity(spans.len()));
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let unit_follows = matches!(tokens.get(i + 1), Some(Token::Variable(name)) if unit(name).is_some());
+
+        if !(matches!(tokens[i], Token::Number(_)) && unit_follows) {
+            grouped.0.push(tokens[i].clone());
+            grouped.1.push(spans[i].clone());
+            i += 1;
+            continue;
+        }
+
+        // the unit may have a power, a number or a bracketed expression
+        let mut end = i + 2;
+
+        if let Some(Token::Operator(Operator::Pow)) = tokens.get(end) {
+            end += 1;
+            let mut depth = 0;
+
+            while let Some(t) = tokens.get(end) {
+                match t {
+                    Token::Bracket(true) => depth += 1,
+                    Token::Bracket(false) => depth -= 1,
+                    _ => {},
+                }
+                end += 1;
+
+                if depth <= 0 {
+                    break;
+                }
+            }
+        }
+
+        let (start, number_end, last) = (spans[i].start, spans[i].end, spans[end - 1].end);
+
+        grouped.0.extend([Token::Bracket(true), tokens[i].clone(), Token::Operator(Operator::Mul)]);
+        grouped.1.extend([start..start, spans[i].clone(), number_end..number_end]);
+        grouped.0.extend(tokens[i + 1..end].iter().cloned());
+        grouped.1.extend(spans[i + 1..end].iter().cloned());
+        grouped.0.push(Token::Bracket(false));
+        grouped.1.push(last..last);
+
+        i = end;
+    }
+
+    ast_from_tokens(grouped)
+}
+/// Evaluates a tree with names resolved to variables of the scope or units.
+pub fn evaluate_quantity(expr: &Expr, scope: &Scope) -> Result<Quantity, String> {
+    expr.try_fold(|e, values: Vec<Quantity>| match &e.kind {
+        ExprKind::Num(n) => Ok(Quantity::number(*n)),
+        ExprKind::Var(name) => {
+            match (scope.get(name), unit(name)) {
+                (Some(n), _) => Ok(Quantity::number(n)),
+                (None, Some((factor, dims))) => Ok(Quantity { value: factor, dims }),
+                (None, None) => Err(format!("Unknown variable or unit: \"{}\"", name)),
+            }
+        },
+        ExprKind::Unary(UnaryOp::Neg, _) => {
+            let q = values[0];
+            Ok(Quantity { value: -q.value, ..q })
+        },
+        ExprKind::Binary(Operator::MatMul, ..) => Err(LISTS.to_string()),
+        ExprKind::Binary(o, ..) if is_comparison(o) => Err(COMPARISONS.to_string()),
+        ExprKind::Binary(o, ..) => {
+            let (a, b) = (values[0], values[1]);
+
+            let dims = match o {
+                Operator::Plus | Operator::Minus if a.dims != b.dims => {
+                    return Err(format!("Dimension mismatch: {} {} {}", dims_name(&a.dims), if *o == Operator::Plus { '+' } else { '-' }, dims_name(&b.dims)));
+                },
+                Operator::Plus | Operator::Minus => a.dims,
+                Operator::Mul => combine(&a.dims, &b.dims, i8::checked_add)?,
+                Operator::MatMul => unreachable!("@ is checked"),
+                Operator::Div => combine(&a.dims, &b.dims, i8::checked_sub)?,
+                Operator::Pow => {
+                    if b.dims != NONE {
+                        return Err(format!("The exponent has to be a number, not {}", dims_name(&b.dims)));
+                    }
+                    power(&a.dims, b.value)?.ok_or_else(|| format!("Cannot raise {} to {}", dims_name(&a.dims), b.value))?
+                },
+                _ => unreachable!("comparisons are checked"),
+            };
+
+            Ok(Quantity { value: apply_operator(o, a.value, b.value), dims })
+        },
+        ExprKind::Call(f, args) => {
+            check_call(f, args, &Validator::new())?;
+
+            let q = values[0];
+
+            let dims = match f {
+                Function::Sqrt => power(&q.dims, 0.5)?.ok_or_else(|| format!("Cannot take the square root of {}", dims_name(&q.dims)))?,
+                Function::Abs => q.dims,
+                Function::Min | Function::Max => {
+                    let other = values[1];
+
+                    if q.dims != other.dims {
+                        return Err(format!("Dimension mismatch: {}({}, {})", function_to_string(f), dims_name(&q.dims), dims_name(&other.dims)));
+                    }
+                    let value = if *f == Function::Min { q.value.min(other.value) } else { q.value.max(other.value) };
+                    return Ok(Quantity { value, dims: q.dims });
+                },
+                _ if q.dims != NONE => return Err(format!("{} needs a number, not {}", function_to_string(f), dims_name(&q.dims))),
+                _ => NONE,
+            };
+
+            Ok(Quantity { value: crate::functions::call_function(f, &[q.value]), dims })
+        },
+        ExprKind::List(_) => Err(LISTS.to_string()),
+        ExprKind::Str(_) => Err(STRINGS.to_string()),
+    })
+}
+/// The error for a dimension with an exponent that doesn't fit in `Dims`.
+const EXPONENT_RANGE: &str = "Unit exponent out of range";
+
+fn combine(a: &Dims, b: &Dims, f: impl Fn(i8, i8) -> Option<i8>) -> Result<Dims, String> {
+    let mut dims = NONE;
+    for (d, (x, y)) in dims.iter_mut().zip(a.iter().zip(b)) {
+        *d = f(*x, *y).ok_or_else(|| EXPONENT_RANGE.to_string())?;
+    }
+    Ok(dims)
+}
+/// The dimensions raised to `n`, `None` unless every exponent stays whole. A number can be raised to anything.
+fn power(dims: &Dims, n: f32) -> Result<Option<Dims>, String> {
+    if *dims == NONE {
+        return Ok(Some(NONE));
+    }
+    let mut res = NONE;
+    for (r, d) in res.iter_mut().zip(dims) {
+        let e = *d as f32 * n;
+        if e.fract() != 0.0 {
+            return Ok(None);
+        }
+        if e < i8::MIN as f32 || e > i8::MAX as f32 {
+            return Err(EXPONENT_RANGE.to_string());
+        }
+        *r = e as i8;
+    }
+    Ok(Some(res))
+}
+
+#[test]
+fn units() {
+    let eval = |exp: &str| eval_units(exp, &Scope::new());
+
+    for (exp, value, unit) in [
+        ("3 km / 15 min to km/h", 12.0, "km/h"),
+        ("3 km / 15 min", 3.3333333, "m/s"),
+        ("5 ft to m", 1.524, "m"),
+        ("2 kg * (3 m/s)^2 / 2 to J", 9.0, "J"),
+        ("3 m^2 * 2 m in L", 6000.0, "L"),
+        ("sqrt(16 m^2)", 4.0, "m"),
+        ("10 N / 2 kg", 5.0, "m/s^2"),
+        ("1 h + 30 min to min", 90.0, "min"),
+        ("min(3 m, 2 ft) to ft", 2.0, "ft"),
+        ("2 pi * 3", 6.0 * std::f32::consts::PI, ""),
+        ("1 / 4 s^(1 + 1)", 0.25, "1/s^2"),
+        ("2 V * 3 A to W", 6.0, "W"),
+        ("sqrt(16)^0.5", 2.0, ""),
+    ] {
+        let m = eval(exp).unwrap_or_else(|err| panic!("{}: {}", exp, err));
+        assert!((m.value - value).abs() < 1e-4 * value, "{}: {} is not {}", exp, m.value, value);
+        assert_eq!(m.unit, unit, "{}", exp);
+    }
+
+    assert_eq!(eval("2 kg * (3 m/s)^2 / 2 to J").unwrap().to_string(), "9 J");
+    assert_eq!(eval("2^(1/0)").unwrap().to_string(), "inf");
+
+    for (exp, err) in [
+        ("2 m + 3 s", "Dimension mismatch: m + s"),
+        ("2 m - 3", "Dimension mismatch: m - a number"),
+        ("max(1 s, 1 m)", "Dimension mismatch: max(s, m)"),
+        ("5 to m", "Dimension mismatch: cannot convert a number to m"),
+        ("sin(2 m)", "sin needs a number, not m"),
+        ("2^(3 m)", "The exponent has to be a number, not m"),
+        ("sqrt(2 m)", "Cannot take the square root of m"),
+        ("m^100 * m^100", "Unit exponent out of range"),
+        ("1 / m^100 / m^100", "Unit exponent out of range"),
+        ("(m^100)^2", "Unit exponent out of range"),
+        ("m^1e10", "Unit exponent out of range"),
+        ("3 parsecs", "Unknown variable or unit: \"parsecs\""),
+    ] {
+        assert_eq!(eval(exp), Err(err.to_string()), "{}", exp);
+    }
+
+    // variables come before units
+    let mut scope = Scope::new();
+    scope.set("h", 2.0);
+    assert_eq!(eval_units("3 h", &scope).unwrap().to_string(), "6");
+
+    assert_eq!(Quantity { value: 2.0, dims: [1, 1, -2, 0, 0, 0, 0] }.to_string(), "2 kg*m/s^2");
+}
@@ -1,118 +1,353 @@
-use crate::token_parse::{Operator, Token};
+use crate::{functions::{argument_count, function_to_string, is_complex_function, is_matrix_function, is_string_function, Function}, token_parse::{is_comparison, operator_to_string, Operator, Span, Token}};
 
-pub fn validate_tokens(tokens: &[Token]) -> Result<(), String> {
+const NONE: i32 = -1;
+const OPEN: i32 = 0;
+const CLOSE: i32 = 1;
+const NUMBER: i32 = 2;
+const OPERATOR: i32 = 3;
+const VARIABLE: i32 = 4;
+const FUNCTION: i32 = 5;
+const COMMA: i32 = 6;
+const STRING: i32 = 7;
+// an operand starts after a comparison, like after an opening bracket
+const COMPARISON: i32 = 8;
+
+/// The error for a list, a matrix function or `@` anywhere but in the matrix evaluator.
+pub(crate) const LISTS: &str = "Lists need the matrix evaluator";
+/// The error for a string or string function anywhere but in the string evaluator.
+pub(crate) const STRINGS: &str = "Strings need the string evaluator";
+/// The error for a function of complex numbers anywhere but in the complex evaluator.
+pub(crate) const COMPLEX: &str = "arg, conj, re and im need the complex evaluator";
+
+/// The error for a comparison anywhere but in the string evaluator.
+pub(crate) const COMPARISONS: &str = "Comparisons need the string evaluator";
 
-    let mut brackets = 0;
-    let mut last_token = NONE;
+/// Default limit for the nesting depth of brackets.
+pub const MAX_DEPTH: usize = 10_000;
+
+pub fn validate_tokens(tokens: &[Token]) -> Result<(), String> {
+    validate_tokens_with_depth(tokens, MAX_DEPTH)
+}
+/// Like `validate_tokens`, with a limit for the nesting depth of brackets.
+pub fn validate_tokens_with_depth(tokens: &[Token], max_depth: usize) -> Result<(), String> {
 
-    const NONE: i32 = -1;
-    const OPEN: i32 = 0;
-    const CLOSE: i32 = 1;
-    const NUMBER: i32 = 2;
-    const OPERATOR: i32 = 3;
+    let mut validator = Validator::with_max_depth(max_depth);
 
     for t in tokens {
+        validator.push(t)?;
+    }
+
+    validator.finish()
+}
+/// Checks tokens one at a time, only the open brackets are kept.
+pub struct Validator {
+    brackets: Vec<Open>,
+    // the bracket depths with a comparison in the argument that is open there
+    compared: Vec<usize>,
+    function: Option<Function>,
+    last_token: i32,
+    max_depth: usize,
+    lists: bool,
+    strings: bool,
+    complex: bool,
+}
+/// An open bracket, a function call with its argument count so far, or a list.
+enum Open {
+    Bracket,
+    Call(Function, usize),
+    List,
+}
+impl Validator {
+    pub fn new() -> Self {
+        Self::with_max_depth(MAX_DEPTH)
+    }
+    /// The evaluators handle any depth, the limit keeps the memory used for open brackets bounded.
+    pub fn with_max_depth(max_depth: usize) -> Self {
+        Validator { brackets: Vec::new(), compared: Vec::new(), function: None, last_token: NONE, max_depth, lists: false, strings: false, complex: false }
+    }
+    /// Like `new`, lists like `[1, 2]` and `[[1, 2], [3, 4]]`, the matrix functions and `@` are allowed, which only the matrix evaluator takes.
+    pub fn with_lists() -> Self {
+        Validator { lists: true, ..Self::new() }
+    }
+    /// Like `new`, strings like `"abc"`, the string functions and comparisons are allowed, which only the string evaluator takes.
+    pub fn with_strings() -> Self {
+        Validator { strings: true, ..Self::new() }
+    }
+    /// Like `new`, the functions of complex numbers `arg`, `conj`, `re` and `im` are allowed, which only the complex evaluator takes.
+    pub fn with_complex() -> Self {
+        Validator { complex: true, ..Self::new() }
+    }
+    /// Checks that the function is allowed, also for trees that weren't validated.
+    pub(crate) fn check_function(&self, f: &Function) -> Result<(), String> {
+        if !self.strings && is_string_function(f) {
+            return Err(STRINGS.to_string());
+        }
+        if !self.complex && is_complex_function(f) {
+            return Err(COMPLEX.to_string());
+        }
+        if !self.lists && is_matrix_function(f) {
+            return Err(LISTS.to_string());
+        }
+        Ok(())
+    }
+    pub fn push(&mut self, t: &Token) -> Result<(), String> {
+
+        let last_token = self.last_token;
+
+        if last_token == FUNCTION && !matches!(t, Token::Bracket(true)) {
+            return Err("Expected opening bracket after function".to_string());
+        }
+        // a string is never multiplied implicitly
+        if last_token == STRING && !matches!(t, Token::Operator(_) | Token::Comma | Token::Bracket(false) | Token::List(false)) {
+            return Err("Expected an operator after string".to_string());
+        }
+
         match t {
             Token::Number(_) => {
                 if last_token == NUMBER { return Err("Unexpected number after another number".to_string()); }
-                last_token = NUMBER;
+                if last_token == VARIABLE { return Err("Unexpected number after variable".to_string()); }
+                self.last_token = NUMBER;
             },
+            Token::Variable(_) => {
+                if last_token == VARIABLE { return Err("Unexpected variable after another variable".to_string()); }
+                self.last_token = VARIABLE;
+            },
+            Token::Function(f) => {
+                self.check_function(f)?;
+                self.function = Some(*f);
+                self.last_token = FUNCTION;
+            },
+            Token::Operator(Operator::MatMul) if !self.lists => return Err(LISTS.to_string()),
+            Token::Operator(o) if is_comparison(o) && !self.strings => return Err(COMPARISONS.to_string()),
             Token::Operator(o) => {
 
-                if last_token == OPERATOR { return Err("Unexpected operator after another operator".to_string()); }
+                if last_token == OPERATOR || (last_token == COMPARISON && *o != Operator::Minus) {
+                    return Err("Unexpected operator after another operator".to_string());
+                }
 
-                if last_token == OPEN {
-                    if *o != Operator::Minus {
-                        return Err("Unexpected operator after opening bracket".to_string());
-                    }
+                if last_token == NONE && *o != Operator::Minus {
+                    return Err("Unexpected operator at the start".to_string());
+                }
+                if last_token == OPEN && *o != Operator::Minus {
+                    return Err("Unexpected operator after opening bracket".to_string());
                 } 
-                last_token = OPERATOR;
+                if last_token == COMMA && *o != Operator::Minus {
+                    return Err("Unexpected operator after comma".to_string());
+                }
+                if is_comparison(o) {
+                    if self.compared.last() == Some(&self.brackets.len()) {
+                        return Err(format!("Unexpected comparison \"{}\" after another comparison", operator_to_string(o)));
+                    }
+                    self.compared.push(self.brackets.len());
+                    self.last_token = COMPARISON;
+                }
+                else {
+                    self.last_token = OPERATOR;
+                }
+            },
+            Token::Comma => {
+                if last_token == OPERATOR || last_token == COMPARISON { return Err("Unexpected comma after operator".to_string()); }
+                if last_token == OPEN || last_token == COMMA { return Err("Missing function argument".to_string()); }
+
+                match self.brackets.last_mut() {
+                    Some(Open::Call(_, args)) => *args += 1,
+                    Some(Open::List) => {},
+                    _ => return Err("Unexpected comma outside of function call".to_string()),
+                }
+                self.end_comparison();
+                self.last_token = COMMA;
             },
             Token::Bracket(b) => {
-                if *b { brackets += 1; last_token = OPEN; }
+                if *b {
+                    let open = self.function.take().map_or(Open::Bracket, |f| Open::Call(f, 1));
+                    self.open(open)?;
+                }
                 else {
-                    if last_token == OPERATOR { return Err("Unexpected closing bracket after operator".to_string()); }
-                    if last_token == OPEN { return Err("Unexpected closing bracket immediately after opening".to_string()); }
-                    if brackets == 0 { return Err("Unexpected closing bracket without opening".to_string()); }
-                    else { brackets -= 1; }
-                    last_token = CLOSE;
+                    match self.close()? {
+                        Open::List => return Err("Expected \"]\" before \")\"".to_string()),
+                        Open::Call(f, args) if args != argument_count(&f) => {
+                            return Err(format!("Function \"{}\" expects {} argument/s", function_to_string(&f), argument_count(&f)));
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            Token::Str(_) if !self.strings => return Err(STRINGS.to_string()),
+            Token::Str(_) => {
+                if last_token == NUMBER || last_token == VARIABLE || last_token == CLOSE {
+                    return Err("Expected an operator before string".to_string());
+                }
+                self.last_token = STRING;
+            },
+            Token::List(_) if !self.lists => return Err(LISTS.to_string()),
+            Token::List(b) => {
+                if *b {
+                    self.open(Open::List)?;
+                }
+                else if !matches!(self.close()?, Open::List) {
+                    return Err("Expected \")\" before \"]\"".to_string());
                 }
             },
         }
+        Ok(())
     }
+    fn open(&mut self, open: Open) -> Result<(), String> {
+
+        if self.brackets.len() == self.max_depth {
+            return Err(format!("Brackets nested deeper than {} levels", self.max_depth));
+        }
+        self.brackets.push(open);
+        self.last_token = OPEN;
 
-    if brackets > 0 {
-        return Err("Unclosed bracket/s".to_string());
+        Ok(())
     }
-    if last_token == OPERATOR {
-        return Err("Unexpected operator at the end".to_string());
+    fn close(&mut self) -> Result<Open, String> {
+
+        let last_token = self.last_token;
+
+        if last_token == OPERATOR || last_token == COMPARISON { return Err("Unexpected closing bracket after operator".to_string()); }
+        if last_token == COMMA { return Err("Missing function argument".to_string()); }
+        if last_token == OPEN { return Err("Unexpected closing bracket immediately after opening".to_string()); }
+
+        self.end_comparison();
+        self.last_token = CLOSE;
+        self.brackets.pop().ok_or_else(|| "Unexpected closing bracket without opening".to_string())
+    }
+    /// Ends the comparison of the open argument, the next argument may have its own.
+    fn end_comparison(&mut self) {
+        if self.compared.last() == Some(&self.brackets.len()) {
+            self.compared.pop();
+        }
     }
+    /// Checks the end of the expression.
+    pub fn finish(&self) -> Result<(), String> {
+
+        if self.last_token == NONE {
+            return Err("Empty expression".to_string());
+        }
+        if !self.brackets.is_empty() {
+            return Err("Unclosed bracket/s".to_string());
+        }
+        if self.last_token == OPERATOR || self.last_token == COMPARISON {
+            return Err("Unexpected operator at the end".to_string());
+        }
+        if self.last_token == FUNCTION {
+            return Err("Expected opening bracket after function".to_string());
+        }
 
-    return Ok(());
+        Ok(())
+    }
+}
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 pub fn add_implicit_tokens(tokens: &mut Vec<Token>) {
 
-    let mut indexes = Vec::new();
-    let mut last_token = NONE;
+    let implicit = implicit_tokens(tokens);
+
+    insert_all(tokens, implicit);
+}
+/// Like `add_implicit_tokens`, an added token gets an empty span at the start of the token it is placed before.
+pub fn add_implicit_tokens_spans(tokens: &mut Vec<Token>, spans: &mut Vec<Span>) {
+
+    let implicit = implicit_tokens(tokens);
+    let implicit_spans = implicit.iter().map(|(i, _)| (*i, spans[*i].start..spans[*i].start)).collect();
+
+    insert_all(tokens, implicit);
+    insert_all(spans, implicit_spans);
+}
+fn implicit_tokens(tokens: &[Token]) -> Vec<(usize, Token)> {
 
-    const UNKNOWN: i32 = -2;
-    const NONE: i32 = -1;
-    const OPEN: i32 = 0;
-    const CLOSE: i32 = 1;
-    const NUMBER: i32 = 2;
-    const OPERATOR: i32 = 3;
+    let mut implicit = Implicit::new();
+
+    tokens.iter().enumerate().filter_map(|(i, t)| implicit.before(t).map(|t| (i, t))).collect()
+}
+/// Finds the multiplications left out before brackets, variables and functions
+/// and the zeros left out before a leading minus, one token at a time.
+pub struct Implicit {
+    last_token: i32,
+}
+impl Implicit {
+    pub fn new() -> Self {
+        Implicit { last_token: NONE }
+    }
+    /// Returns the token left out before `t`.
+    pub fn before(&mut self, t: &Token) -> Option<Token> {
+
+        let last_token = self.last_token;
+        let mut res = None;
 
-    for (i, t) in tokens.iter().enumerate() {
         match t {
             Token::Number(_) => {
-                if last_token == CLOSE { indexes.push(i); }
-                last_token = NUMBER;
+                if last_token == CLOSE { res = Some(Token::Operator(Operator::Mul)); }
+                self.last_token = NUMBER;
             },
-            Token::Operator(_) => {
-                last_token = OPERATOR;
+            Token::Variable(_) => {
+                if last_token == NUMBER || last_token == CLOSE { res = Some(Token::Operator(Operator::Mul)); }
+                self.last_token = VARIABLE;
             },
-            Token::Bracket(b) => {
-                if (last_token == NUMBER || last_token == CLOSE) && *b { 
-                    indexes.push(i);
+            Token::Function(_) => {
+                if last_token == NUMBER || last_token == VARIABLE || last_token == CLOSE { res = Some(Token::Operator(Operator::Mul)); }
+                self.last_token = FUNCTION;
+            },
+            Token::Operator(o) => {
+                // an argument starts like a bracket
+                if *o == Operator::Minus && (last_token == NONE || last_token == OPEN || last_token == COMMA || last_token == COMPARISON) {
+                    res = Some(Token::Number(0.0));
                 }
-                if *b { last_token = OPEN; }
-                else { last_token = CLOSE; }
+                self.last_token = if is_comparison(o) { COMPARISON } else { OPERATOR };
+            },
+            Token::Comma => {
+                self.last_token = COMMA;
+            },
+            Token::Str(_) => {
+                self.last_token = STRING;
+            },
+            Token::Bracket(b) | Token::List(b) => {
+                if (last_token == NUMBER || last_token == VARIABLE || last_token == CLOSE) && *b { 
+                    res = Some(Token::Operator(Operator::Mul));
+                }
+                if *b { self.last_token = OPEN; }
+                else { self.last_token = CLOSE; }
             },
         }
+        res
     }
-
-    tokens.reserve(indexes.len());
-
-    for i in indexes.iter().rev() {
-        tokens.insert(*i, Token::Operator(Operator::Mul));
+}
+impl Default for Implicit {
+    fn default() -> Self {
+        Self::new()
     }
+}
+/// Inserts the items before the elements at their index, `items` is sorted by index.
+fn insert_all<T>(vec: &mut Vec<T>, items: Vec<(usize, T)>) {
 
-    last_token = -1;
-    indexes.clear();
+    if items.is_empty() {
+        return;
+    }
 
-    for (i, t) in tokens.iter().enumerate() {
+    let mut res = Vec::with_capacity(vec.len() + items.len());
+    let mut items = items.into_iter().peekable();
 
-        if let Token::Operator(o) = t {
-            if *o == Operator::Minus {
-                if last_token == NONE { indexes.push(i); }
-                if last_token == OPEN { indexes.push(i); }
-            }  
-        }
-        
-        if let Token::Bracket(b) = t {
-            if *b { 
-                last_token = OPEN; 
-                continue;
-            }
+    for (i, t) in vec.drain(..).enumerate() {
+        while let Some((_, item)) = items.next_if(|x| x.0 == i) {
+            res.push(item);
         }
-        last_token = UNKNOWN;
+        res.push(t);
     }
 
-    tokens.reserve(indexes.len());
+    *vec = res;
+}
 
-    
+#[test]
+fn leading_operators() {
+    use crate::eval_exp;
 
-    for i in indexes.iter().rev() {
-        tokens.insert(*i, Token::Number(0.0));
+    assert_eq!(eval_exp("-5"), Ok(-5.0));
+
+    for exp in ["+5", "*5", "/5", "^5", "+ 5 * 2"] {
+        assert_eq!(eval_exp(exp), Err("Unexpected operator at the start".to_string()), "{}", exp);
     }
-}
\ No newline at end of file
+}